@@ -0,0 +1,99 @@
+//! Traits implemented by message brokers.
+
+use std::future::Future;
+
+use signaly_error::Error;
+
+/// Position of a received message in its broker.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Origin {
+    /// Kafka topic or RabbitMQ queue.
+    pub topic: String,
+    /// Kafka partition.
+    pub partition: Option<i32>,
+    /// Kafka offset.
+    pub offset: Option<i64>,
+    /// RabbitMQ delivery tag.
+    pub delivery_tag: Option<u64>,
+}
+
+/// Message received from a broker.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    /// Where the message comes from.
+    pub origin: Origin,
+    /// Content of the message.
+    pub payload: Vec<u8>,
+}
+
+/// Message sent to a broker.
+#[derive(Debug, Clone)]
+pub struct Message<'a> {
+    /// Kafka key, messages with the same key are sent to the same
+    /// partition.
+    pub key: Option<&'a str>,
+    /// RabbitMQ routing key, such as `sanction.removal`.
+    pub routing_key: String,
+    /// Serialized event.
+    pub content: String,
+}
+
+/// What to do with a received message once handled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Acknowledgement {
+    /// The message has been processed.
+    Ack,
+    /// The message could not be processed and must be delivered again.
+    Requeue,
+    /// The message cannot be processed and must not be delivered again.
+    Reject,
+}
+
+/// Broker on which messages are published.
+pub trait Publisher: Send + Sync {
+    /// Name of the broker.
+    fn name(&self) -> &'static str;
+
+    /// Send `message` to `topic`, or to the queue or exchange configured
+    /// for RabbitMQ.
+    fn publish(
+        &self,
+        topic: &str,
+        message: Message<'_>,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Check that the broker can be reached, without sending anything.
+    fn check_health(&self) -> impl Future<Output = bool> + Send;
+}
+
+/// Broker from which messages are received.
+pub trait Subscriber: Send + 'static {
+    /// Whether messages can be given back to the broker with
+    /// [`Acknowledgement::Requeue`] and [`Acknowledgement::Reject`].
+    /// Otherwise, failed messages must be retried until they are processed.
+    const REQUEUES: bool;
+
+    /// Name of the broker.
+    fn name(&self) -> &'static str;
+
+    /// Wait for the next messages.
+    ///
+    /// Messages of a same Kafka partition are ordered. An error means that
+    /// the connection was lost: the next call reconnects.
+    fn receive(
+        &mut self,
+    ) -> impl Future<Output = Result<Vec<Delivery>, Error>> + Send;
+
+    /// Tell the broker how the message at `origin` has been handled.
+    fn acknowledge(
+        &mut self,
+        origin: &Origin,
+        acknowledgement: Acknowledgement,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Save acknowledgements not sent yet to the broker, before the
+    /// subscriber is dropped.
+    fn close(&mut self) -> impl Future<Output = Result<(), Error>> + Send {
+        async { Ok(()) }
+    }
+}
@@ -0,0 +1,659 @@
+//! Versioned schema migrations.
+//!
+//! Migrations are applied in order of version. Each applied version is
+//! recorded in `schema_migrations` so it is never applied twice. Steps are
+//! safe to run again, so a migration interrupted halfway can be resumed.
+
+use std::fmt;
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use scylla::{query::Query, Bytes};
+use signaly_error::{DatabaseError::QueryFailed, Error, ErrorType};
+use uuid::Uuid;
+
+use super::{end_day, query_error, rows_error, Manager, INSERT_SANCTION_END};
+
+/// Reason names saved in `sanctions.reason`, indexed by their code.
+///
+/// Must stay in sync with the reasons accepted by Signaly.
+const REASONS: [&str; 8] = [
+    "Copyright",
+    "Defamation",
+    "Hate",
+    "Harassment",
+    "Nudity",
+    "Spam",
+    "Violence",
+    "Other",
+];
+/// Code of the `Other` reason, used for unknown names.
+const OTHER_REASON: i32 = 7;
+/// Number of rows read at once while copying data.
+const BATCH_SIZE: i32 = 500;
+/// Time to live of reports, in seconds.
+const REPORT_TTL: i32 = 2592000;
+
+/// Single change made by a [`Migration`].
+#[derive(Debug)]
+pub enum Step {
+    /// Idempotent CQL statement, such as `CREATE TABLE IF NOT EXISTS`.
+    Cql(&'static str),
+    /// Add a column if it does not exist yet.
+    AddColumn {
+        /// Table to alter.
+        table: &'static str,
+        /// Name of the new column.
+        column: &'static str,
+        /// CQL type of the new column.
+        cql_type: &'static str,
+    },
+    /// Drop a column if it still exists.
+    DropColumn {
+        /// Table to alter.
+        table: &'static str,
+        /// Name of the dropped column.
+        column: &'static str,
+    },
+    /// Convert the reason names of sanctions into numeric codes.
+    SanctionReasonCodes,
+    /// Save the end of existing sanctions in `sanctions_by_end`.
+    SanctionEnds,
+    /// Copy existing reports into `reports_by_target`.
+    ReportsByTarget,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::Cql(statement) => write!(f, "{}", statement.trim()),
+            Step::AddColumn {
+                table,
+                column,
+                cql_type,
+            } => write!(f, "ALTER TABLE {} ADD {} {};", table, column, cql_type),
+            Step::DropColumn { table, column } => {
+                write!(f, "ALTER TABLE {} DROP {};", table, column)
+            },
+            Step::SanctionReasonCodes => write!(
+                f,
+                "-- Copy sanctions.reason into sanctions.reason_code and sanctions.text_reason."
+            ),
+            Step::SanctionEnds => write!(
+                f,
+                "-- Copy sanctions.expires_at into sanctions_by_end."
+            ),
+            Step::ReportsByTarget => {
+                write!(f, "-- Copy reports into reports_by_target.")
+            },
+        }
+    }
+}
+
+/// Ordered set of changes applied to the schema.
+#[derive(Debug)]
+pub struct Migration {
+    /// Unique version, greater than the previous migration.
+    pub version: i32,
+    /// Short description of the changes.
+    pub description: &'static str,
+    /// Changes applied in order.
+    pub steps: &'static [Step],
+}
+
+/// Every migration of the schema, ordered by version.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create reports and sanctions tables",
+        steps: &[
+            Step::Cql(
+                r#"
+                CREATE TABLE IF NOT EXISTS reports (
+                    id          UUID,
+                    date        DATE,
+                    source      TEXT,
+                    target      TEXT,
+                    reason      INT,
+                    text_reason TEXT,
+                    PRIMARY KEY (id) )
+                WITH default_time_to_live = 2592000;
+                "#,
+            ),
+            Step::Cql(
+                r#"
+                CREATE TABLE IF NOT EXISTS sanctions (
+                    id          UUID,
+                    date        DATE,
+                    source      TEXT,
+                    target      TEXT,
+                    reason      TEXT,
+                    sanction    INT,
+                    PRIMARY KEY (id) );
+                "#,
+            ),
+            Step::Cql("CREATE INDEX IF NOT EXISTS ON reports ( target );"),
+            Step::Cql("CREATE INDEX IF NOT EXISTS ON sanctions ( target );"),
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "Add expiry and status to sanctions",
+        steps: &[
+            Step::AddColumn {
+                table: "sanctions",
+                column: "expires_at",
+                cql_type: "TIMESTAMP",
+            },
+            Step::AddColumn {
+                table: "sanctions",
+                column: "status",
+                cql_type: "INT",
+            },
+            Step::Cql("CREATE INDEX IF NOT EXISTS ON sanctions ( status );"),
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "Save reasons of sanctions as numeric codes",
+        steps: &[
+            Step::AddColumn {
+                table: "sanctions",
+                column: "reason_code",
+                cql_type: "INT",
+            },
+            Step::AddColumn {
+                table: "sanctions",
+                column: "text_reason",
+                cql_type: "TEXT",
+            },
+            Step::SanctionReasonCodes,
+            // A dropped column cannot be added again with another type.
+            Step::DropColumn {
+                table: "sanctions",
+                column: "reason",
+            },
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "Partition reports by target and day",
+        steps: &[
+            Step::AddColumn {
+                table: "reports",
+                column: "time",
+                cql_type: "TIMESTAMP",
+            },
+            Step::Cql(
+                r#"
+                CREATE TABLE IF NOT EXISTS reports_by_target (
+                    target      TEXT,
+                    day         DATE,
+                    time        TIMESTAMP,
+                    id          UUID,
+                    source      TEXT,
+                    reason      INT,
+                    text_reason TEXT,
+                    PRIMARY KEY ((target, day), time, id) )
+                WITH CLUSTERING ORDER BY (time DESC, id ASC)
+                AND default_time_to_live = 2592000;
+                "#,
+            ),
+            Step::ReportsByTarget,
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "Record processed events to ignore duplicates",
+        steps: &[Step::Cql(
+            r#"
+            CREATE TABLE IF NOT EXISTS processed_events (
+                source      TEXT,
+                id          TEXT,
+                received_at TIMESTAMP,
+                PRIMARY KEY ((source, id)) )
+            WITH default_time_to_live = 604800;
+            "#,
+        )],
+    },
+    Migration {
+        version: 6,
+        description: "Save messages which cannot be processed",
+        steps: &[Step::Cql(
+            r#"
+            CREATE TABLE IF NOT EXISTS dead_letters (
+                day         DATE,
+                received_at TIMESTAMP,
+                id          UUID,
+                payload     BLOB,
+                error       TEXT,
+                PRIMARY KEY ((day), received_at, id) )
+            WITH CLUSTERING ORDER BY (received_at DESC, id ASC)
+            AND default_time_to_live = 2592000;
+            "#,
+        )],
+    },
+    Migration {
+        version: 7,
+        description: "Record where dead letters were received from",
+        steps: &[
+            Step::AddColumn {
+                table: "dead_letters",
+                column: "topic",
+                cql_type: "TEXT",
+            },
+            Step::AddColumn {
+                table: "dead_letters",
+                column: "kafka_partition",
+                cql_type: "INT",
+            },
+            Step::AddColumn {
+                table: "dead_letters",
+                column: "kafka_offset",
+                cql_type: "BIGINT",
+            },
+            Step::AddColumn {
+                table: "dead_letters",
+                column: "delivery_tag",
+                cql_type: "BIGINT",
+            },
+        ],
+    },
+    Migration {
+        version: 8,
+        description: "Record the brokers each message was delivered to",
+        steps: &[Step::Cql(
+            r#"
+            CREATE TABLE IF NOT EXISTS deliveries (
+                message_id   UUID,
+                broker       TEXT,
+                delivered_at TIMESTAMP,
+                PRIMARY KEY ((message_id), broker) )
+            WITH default_time_to_live = 604800;
+            "#,
+        )],
+    },
+    Migration {
+        version: 9,
+        description: "Index sanctions by end to expire them",
+        steps: &[
+            Step::Cql(
+                r#"
+                CREATE TABLE IF NOT EXISTS sanctions_by_end (
+                    day        DATE,
+                    expires_at TIMESTAMP,
+                    id         UUID,
+                    PRIMARY KEY ((day), expires_at, id) );
+                "#,
+            ),
+            Step::SanctionEnds,
+        ],
+    },
+    Migration {
+        version: 10,
+        description: "Save changes of status until they are announced",
+        steps: &[Step::Cql(
+            r#"
+            CREATE TABLE IF NOT EXISTS pending_announcements (
+                day         DATE,
+                created_at  TIMESTAMP,
+                sanction_id UUID,
+                status      INT,
+                PRIMARY KEY ((day), created_at, sanction_id, status) )
+            WITH default_time_to_live = 2592000;
+            "#,
+        )],
+    },
+];
+
+/// State of a [`Migration`] in the database.
+#[derive(Debug)]
+pub struct MigrationStatus {
+    /// Known migration.
+    pub migration: &'static Migration,
+    /// Time the migration was applied, `None` if pending.
+    pub applied_at: Option<DateTime<Utc>>,
+}
+
+impl Manager {
+    /// Get the state of every known migration.
+    ///
+    /// Does not change the schema, so it can be used for a dry run.
+    pub async fn migration_status(
+        &self,
+    ) -> Result<Vec<MigrationStatus>, Error> {
+        let applied: Vec<(i32, DateTime<Utc>)> =
+            if self.table_exists("schema_migrations").await? {
+                self.connection
+                    .query(
+                        "SELECT version, applied_at FROM schema_migrations;",
+                        &[],
+                    )
+                    .await
+                    .map_err(|error| {
+                        query_error(error, "while getting applied migrations")
+                    })?
+                    .rows_typed_or_empty::<(i32, DateTime<Utc>)>()
+                    .collect::<Result<_, _>>()
+                    .map_err(|error| {
+                        rows_error(error, "while reading applied migrations")
+                    })?
+            } else {
+                Vec::new()
+            };
+
+        Ok(MIGRATIONS
+            .iter()
+            .map(|migration| MigrationStatus {
+                migration,
+                applied_at: applied
+                    .iter()
+                    .find(|(version, _)| *version == migration.version)
+                    .map(|(_, applied_at)| *applied_at),
+            })
+            .collect())
+    }
+
+    /// Apply pending migrations in order.
+    ///
+    /// Returns the applied migrations.
+    pub async fn migrate(&self) -> Result<Vec<&'static Migration>, Error> {
+        self.connection
+            .query(
+                r#"
+                CREATE TABLE IF NOT EXISTS schema_migrations (
+                    version     INT,
+                    description TEXT,
+                    applied_at  TIMESTAMP,
+                    PRIMARY KEY (version) );
+                "#,
+                &[],
+            )
+            .await
+            .map_err(|error| {
+                query_error(error, "while creating schema_migrations table")
+            })?;
+
+        let mut applied = Vec::new();
+        for status in self.migration_status().await? {
+            if status.applied_at.is_some() {
+                continue;
+            }
+
+            let migration = status.migration;
+            for step in migration.steps {
+                self.apply(step).await.map_err(|mut error| {
+                    error.context = Some(format!(
+                        "while applying migration {} ({})",
+                        migration.version,
+                        error.context.unwrap_or_default()
+                    ));
+                    error
+                })?;
+            }
+
+            self.connection
+                .query(
+                    "INSERT INTO schema_migrations (version, description, applied_at) VALUES (?, ?, ?);",
+                    (migration.version, migration.description, Utc::now()),
+                )
+                .await
+                .map_err(|error| {
+                    query_error(error, "while recording a migration")
+                })?;
+
+            applied.push(migration);
+        }
+
+        Ok(applied)
+    }
+
+    async fn apply(&self, step: &Step) -> Result<(), Error> {
+        match step {
+            Step::Cql(statement) => {
+                self.connection
+                    .query(*statement, &[])
+                    .await
+                    .map_err(|error| query_error(error, "while running CQL"))?;
+            },
+            Step::AddColumn { table, column, .. } => {
+                if !self.column_exists(table, column).await? {
+                    self.connection
+                        .query(step.to_string(), &[])
+                        .await
+                        .map_err(|error| {
+                            query_error(error, "while adding a column")
+                        })?;
+                }
+            },
+            Step::DropColumn { table, column } => {
+                if self.column_exists(table, column).await? {
+                    self.connection
+                        .query(step.to_string(), &[])
+                        .await
+                        .map_err(|error| {
+                            query_error(error, "while dropping a column")
+                        })?;
+                }
+            },
+            Step::SanctionReasonCodes => {
+                if self.column_exists("sanctions", "reason").await? {
+                    self.copy_sanction_reasons().await?;
+                }
+            },
+            Step::SanctionEnds => self.copy_sanction_ends().await?,
+            Step::ReportsByTarget => self.copy_reports_by_target().await?,
+        }
+
+        Ok(())
+    }
+
+    /// Fill `reason_code` and `text_reason` of sanctions from `reason`.
+    async fn copy_sanction_reasons(&self) -> Result<(), Error> {
+        let mut paging_state: Option<Bytes> = None;
+
+        loop {
+            let result = self
+                .connection
+                .query_paged(
+                    Query::new(
+                        "SELECT id, reason, reason_code FROM sanctions;",
+                    )
+                    .with_page_size(BATCH_SIZE),
+                    &[],
+                    paging_state,
+                )
+                .await
+                .map_err(|error| {
+                    query_error(error, "while reading sanction reasons")
+                })?;
+            paging_state = result.paging_state.clone();
+
+            for row in result
+                .rows_typed_or_empty::<(Uuid, Option<String>, Option<i32>)>()
+            {
+                let (id, reason, code) = row.map_err(|error| {
+                    rows_error(error, "while reading sanction reasons")
+                })?;
+                if code.is_some() {
+                    continue;
+                }
+
+                let reason = reason.unwrap_or_default();
+                let (code, text_reason) =
+                    match REASONS.iter().position(|name| *name == reason) {
+                        Some(code) => (code as i32, None),
+                        None => (OTHER_REASON, Some(reason)),
+                    };
+
+                self.connection
+                    .query(
+                        "UPDATE sanctions SET reason_code = ?, text_reason = ? WHERE id = ?;",
+                        (code, text_reason, id),
+                    )
+                    .await
+                    .map_err(|error| {
+                        query_error(error, "while saving a sanction reason")
+                    })?;
+            }
+
+            if paging_state.is_none() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Fill `sanctions_by_end` from the end of sanctions.
+    async fn copy_sanction_ends(&self) -> Result<(), Error> {
+        let mut paging_state: Option<Bytes> = None;
+
+        loop {
+            let result = self
+                .connection
+                .query_paged(
+                    Query::new("SELECT id, expires_at FROM sanctions;")
+                        .with_page_size(BATCH_SIZE),
+                    &[],
+                    paging_state,
+                )
+                .await
+                .map_err(|error| {
+                    query_error(error, "while reading sanction ends")
+                })?;
+            paging_state = result.paging_state.clone();
+
+            for row in
+                result.rows_typed_or_empty::<(Uuid, Option<DateTime<Utc>>)>()
+            {
+                let (id, expires_at) = row.map_err(|error| {
+                    rows_error(error, "while reading sanction ends")
+                })?;
+                let Some(expires_at) = expires_at else {
+                    continue;
+                };
+
+                self.connection
+                    .query(
+                        INSERT_SANCTION_END,
+                        (end_day(expires_at), expires_at, id),
+                    )
+                    .await
+                    .map_err(|error| {
+                        query_error(error, "while saving a sanction end")
+                    })?;
+            }
+
+            if paging_state.is_none() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Fill `reports_by_target` from `reports`, keeping the remaining time
+    /// to live of each report.
+    async fn copy_reports_by_target(&self) -> Result<(), Error> {
+        type Row = (
+            Uuid,
+            Option<NaiveDate>,
+            Option<DateTime<Utc>>,
+            Option<String>,
+            Option<String>,
+            Option<i32>,
+            Option<String>,
+            Option<i32>,
+        );
+        let mut paging_state: Option<Bytes> = None;
+
+        loop {
+            let result = self
+                .connection
+                .query_paged(
+                    Query::new(
+                        "SELECT id, date, time, source, target, reason, text_reason, TTL(target) FROM reports;",
+                    )
+                    .with_page_size(BATCH_SIZE),
+                    &[],
+                    paging_state,
+                )
+                .await
+                .map_err(|error| query_error(error, "while reading reports"))?;
+            paging_state = result.paging_state.clone();
+
+            for row in result.rows_typed_or_empty::<Row>() {
+                let (id, date, time, source, target, reason, text_reason, ttl) =
+                    row.map_err(|error| {
+                        rows_error(error, "while reading reports")
+                    })?;
+                let (Some(date), Some(target)) = (date, target) else {
+                    continue;
+                };
+                // Reports without time are saved at the beginning of their
+                // day, as by `Manager::insert_report`.
+                let time = time
+                    .unwrap_or_else(|| date.and_time(NaiveTime::MIN).and_utc());
+
+                self.connection
+                    .query(
+                        "INSERT INTO reports_by_target (target, day, time, id, source, reason, text_reason) VALUES (?, ?, ?, ?, ?, ?, ?) USING TTL ?;",
+                        (
+                            target,
+                            time.date_naive(),
+                            time,
+                            id,
+                            source,
+                            reason,
+                            text_reason,
+                            ttl.unwrap_or(REPORT_TTL),
+                        ),
+                    )
+                    .await
+                    .map_err(|error| {
+                        query_error(error, "while copying a report")
+                    })?;
+            }
+
+            if paging_state.is_none() {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn table_exists(&self, table: &str) -> Result<bool, Error> {
+        let keyspace = self.keyspace()?;
+
+        self.connection
+            .query(
+                "SELECT table_name FROM system_schema.tables WHERE keyspace_name = ? AND table_name = ?;",
+                (keyspace.as_str(), table),
+            )
+            .await
+            .map_err(|error| query_error(error, "while reading the schema"))
+            .map(|result| result.rows_num().unwrap_or_default() > 0)
+    }
+
+    async fn column_exists(
+        &self,
+        table: &str,
+        column: &str,
+    ) -> Result<bool, Error> {
+        let keyspace = self.keyspace()?;
+
+        self.connection
+            .query(
+                "SELECT column_name FROM system_schema.columns WHERE keyspace_name = ? AND table_name = ? AND column_name = ?;",
+                (keyspace.as_str(), table, column),
+            )
+            .await
+            .map_err(|error| query_error(error, "while reading the schema"))
+            .map(|result| result.rows_num().unwrap_or_default() > 0)
+    }
+
+    fn keyspace(&self) -> Result<std::sync::Arc<String>, Error> {
+        self.connection.get_keyspace().ok_or_else(|| {
+            Error::new(
+                ErrorType::Database(QueryFailed),
+                None,
+                Some("no keyspace is used by the session".to_string()),
+            )
+        })
+    }
+}
@@ -0,0 +1,927 @@
+//! Apache Cassandra and ScyllaDB pool connection handler.
+//!
+//! The schema is created and updated by [`migration`].
+
+pub mod migration;
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use scylla::{
+    batch::Batch,
+    frame::{response::result::CqlValue, Compression},
+    query::Query,
+    serialize::row::SerializeRow,
+    transport::{
+        errors::{NewSessionError, QueryError},
+        session::PoolSize,
+    },
+    Bytes, FromRow, QueryResult, Session, SessionBuilder,
+};
+use signaly_error::{
+    DatabaseError::{InvalidRows, PoolCreation, QueryFailed},
+    Error, ErrorType,
+};
+use std::{
+    cmp::Reverse, collections::BTreeMap, fmt, num::NonZeroUsize, str::FromStr,
+};
+use uuid::Uuid;
+
+use crate::storage::Storage;
+pub use crate::storage::{DeadLetter, Report, Sanction};
+
+/// Default name of the keyspace used by Signaly.
+pub const DEFAULT_KEYSPACE: &str = "compliance";
+
+/// Maximum number of days, thus of partitions, read by a query.
+const MAX_DAYS: usize = 366;
+
+/// Rows read at once when counting reports.
+const COUNT_PAGE_SIZE: i32 = 5000;
+
+/// Statement saving an end of sanction in `sanctions_by_end` table.
+const INSERT_SANCTION_END: &str =
+    "INSERT INTO sanctions_by_end (day, expires_at, id) VALUES (?, ?, ?);";
+
+/// End of a sanction saved in `sanctions_by_end` table.
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct SanctionEnd {
+    /// Day the end is filed under.
+    pub day: NaiveDate,
+    /// End of the sanction.
+    pub expires_at: DateTime<Utc>,
+    /// Identifier of the sanction.
+    pub id: Uuid,
+}
+
+/// Announcement of a change of status not yet sent, saved in
+/// `pending_announcements` table.
+#[derive(Debug, Clone, PartialEq, FromRow)]
+pub struct PendingAnnouncement {
+    /// Day the change was made.
+    pub day: NaiveDate,
+    /// Time the change was made.
+    pub created_at: DateTime<Utc>,
+    /// Identifier of the changed sanction.
+    pub sanction_id: Uuid,
+    /// Numeric code of the new status.
+    pub status: i32,
+}
+
+impl PendingAnnouncement {
+    /// Create an announcement of `status` for the sanction `sanction_id`.
+    pub fn new(sanction_id: Uuid, status: i32) -> Self {
+        let created_at = Utc::now();
+
+        PendingAnnouncement {
+            day: created_at.date_naive(),
+            created_at,
+            sanction_id,
+            status,
+        }
+    }
+}
+
+/// Rows of a paginated query.
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    /// Rows of the current page.
+    pub rows: Vec<T>,
+    /// State to pass to get the following page, `None` on the last page.
+    pub paging_state: Option<Vec<u8>>,
+}
+
+/// Replication strategy of a keyspace.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Replication {
+    /// Same number of replicas in the whole cluster.
+    Simple(u32),
+    /// Number of replicas per datacenter.
+    NetworkTopology(BTreeMap<String, u32>),
+}
+
+impl fmt::Display for Replication {
+    /// Format the replication as a CQL map.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Replication::Simple(factor) => write!(
+                f,
+                "{{'class': 'SimpleStrategy', 'replication_factor': {}}}",
+                factor
+            ),
+            Replication::NetworkTopology(datacenters) => {
+                write!(f, "{{'class': 'NetworkTopologyStrategy'")?;
+                for (datacenter, factor) in datacenters {
+                    write!(
+                        f,
+                        ", '{}': {}",
+                        datacenter.replace('\'', "''"),
+                        factor
+                    )?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+impl FromStr for Replication {
+    type Err = String;
+
+    /// Parse either a replication factor, such as `3`, or replication
+    /// factors per datacenter, such as `dc1:3,dc2:2`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parse_factor = |factor: &str| {
+            factor
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|factor| *factor > 0)
+                .ok_or_else(|| {
+                    format!("invalid replication factor {:?}", factor)
+                })
+        };
+
+        if !value.contains(':') {
+            return parse_factor(value).map(Replication::Simple);
+        }
+
+        value
+            .split(',')
+            .map(|datacenter| match datacenter.split_once(':') {
+                Some((name, factor)) if !name.trim().is_empty() => {
+                    Ok((name.trim().to_string(), parse_factor(factor)?))
+                },
+                _ => Err(format!(
+                    "invalid datacenter replication {:?}",
+                    datacenter
+                )),
+            })
+            .collect::<Result<_, _>>()
+            .map(Replication::NetworkTopology)
+    }
+}
+
+/// Keyspace used by Signaly.
+#[derive(Debug, Clone)]
+pub struct Keyspace {
+    /// Case sensitive name of the keyspace.
+    pub name: String,
+    /// Replication used to create the keyspace if it does not exist.
+    /// If not set, the keyspace must already exist.
+    pub replication: Option<Replication>,
+}
+
+impl Default for Keyspace {
+    fn default() -> Self {
+        Keyspace {
+            name: DEFAULT_KEYSPACE.to_string(),
+            replication: None,
+        }
+    }
+}
+
+/// Manage Apache Cassandra or Scylla pool connection.
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct Manager {
+    /// Pool session.
+    connection: Session,
+}
+
+impl Manager {
+    /// Create a new pool of connections.
+    ///
+    /// The keyspace is created first if its replication is set.
+    ///
+    /// # Examples
+    /// ```rust
+    /// use signaly_db::cassandra::{Keyspace, Manager as ScyllaManager};
+    ///
+    /// let session = ScyllaManager::new(
+    ///     vec!["127.0.0.1:9042".to_string()],
+    ///     Some("cassandra".to_string()),
+    ///     Some("cassandra".to_string()),
+    ///     10,
+    ///     Keyspace::default(),
+    /// );
+    ///
+    /// // Do what ever you want with your cool new session...
+    /// ```
+    pub async fn new(
+        hosts: Vec<String>,
+        username: Option<String>,
+        password: Option<String>,
+        pool_size: usize,
+        keyspace: Keyspace,
+    ) -> Result<Self, Error> {
+        let pool_size = NonZeroUsize::new(pool_size).ok_or_else(|| {
+            Error::new(
+                ErrorType::Database(PoolCreation),
+                None,
+                Some("pool size must be at least 1".to_string()),
+            )
+        })?;
+
+        let session = SessionBuilder::new()
+            .known_nodes(hosts)
+            .user(username.unwrap_or_default(), password.unwrap_or_default())
+            .pool_size(PoolSize::PerHost(pool_size))
+            .compression(Some(Compression::Lz4))
+            // Activate (true) if the application becomes bigger.
+            // It should reduce latency if false, and increase write/read throughput if true.
+            .write_coalescing(false)
+            .build()
+            .await
+            .map_err(session_error)?;
+
+        if let Some(replication) = &keyspace.replication {
+            session
+                .query(
+                    format!(
+                        "CREATE KEYSPACE IF NOT EXISTS \"{}\" WITH replication = {};",
+                        keyspace.name.replace('"', "\"\""),
+                        replication
+                    ),
+                    &[],
+                )
+                .await
+                .map_err(|error| {
+                    query_error(error, "while creating the keyspace")
+                })?;
+        }
+
+        session
+            .use_keyspace(keyspace.name, true)
+            .await
+            .map_err(|error| query_error(error, "while using the keyspace"))?;
+
+        Ok(Manager {
+            connection: session,
+        })
+    }
+
+    /// Save a report in `reports` and `reports_by_target` tables.
+    ///
+    /// Reports without time are saved at the beginning of their day.
+    pub async fn insert_report(&self, report: &Report) -> Result<(), Error> {
+        let time = report
+            .time
+            .unwrap_or_else(|| report.date.and_time(NaiveTime::MIN).and_utc());
+
+        let mut batch = Batch::default();
+        batch.append_statement(
+            "INSERT INTO reports (id, date, time, source, target, reason, text_reason) VALUES (?, ?, ?, ?, ?, ?, ?);",
+        );
+        batch.append_statement(
+            "INSERT INTO reports_by_target (target, day, time, id, source, reason, text_reason) VALUES (?, ?, ?, ?, ?, ?, ?);",
+        );
+
+        self.connection
+            .batch(
+                &batch,
+                (
+                    (
+                        report.id,
+                        report.date,
+                        time,
+                        &report.source,
+                        &report.target,
+                        report.reason,
+                        &report.text_reason,
+                    ),
+                    (
+                        &report.target,
+                        time.date_naive(),
+                        time,
+                        report.id,
+                        &report.source,
+                        report.reason,
+                        &report.text_reason,
+                    ),
+                ),
+            )
+            .await
+            .map_err(|error| query_error(error, "while saving a report"))?;
+
+        Ok(())
+    }
+
+    /// Save a sanction in `sanctions` table.
+    ///
+    /// Its end, if any, is also saved in `sanctions_by_end` table.
+    pub async fn insert_sanction(
+        &self,
+        sanction: &Sanction,
+    ) -> Result<(), Error> {
+        const INSERT: &str = "INSERT INTO sanctions (id, date, source, target, reason_code, text_reason, sanction, expires_at, status) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?);";
+
+        let values = (
+            sanction.id,
+            sanction.date,
+            &sanction.source,
+            &sanction.target,
+            sanction.reason,
+            &sanction.text_reason,
+            sanction.sanction,
+            sanction.expires_at,
+            sanction.status,
+        );
+
+        match sanction.expires_at {
+            Some(expires_at) => {
+                let mut batch = Batch::default();
+                batch.append_statement(INSERT);
+                batch.append_statement(INSERT_SANCTION_END);
+
+                self.connection
+                    .batch(
+                        &batch,
+                        (
+                            values,
+                            (end_day(expires_at), expires_at, sanction.id),
+                        ),
+                    )
+                    .await
+            },
+            None => self.connection.query(INSERT, values).await,
+        }
+        .map_err(|error| query_error(error, "while saving a sanction"))?;
+
+        Ok(())
+    }
+
+    /// Save a message in `dead_letters` table.
+    pub async fn insert_dead_letter(
+        &self,
+        letter: &DeadLetter,
+    ) -> Result<(), Error> {
+        self.connection
+            .query(
+                "INSERT INTO dead_letters (day, received_at, id, payload, error, topic, kafka_partition, kafka_offset, delivery_tag) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?);",
+                (
+                    letter.received_at.date_naive(),
+                    letter.received_at,
+                    letter.id,
+                    &letter.payload,
+                    &letter.error,
+                    &letter.topic,
+                    letter.partition,
+                    letter.offset,
+                    letter.delivery_tag,
+                ),
+            )
+            .await
+            .map_err(|error| query_error(error, "while saving a dead letter"))?;
+
+        Ok(())
+    }
+
+    /// Get messages saved in `dead_letters` table between `from` and `to`
+    /// included, most recent first.
+    pub async fn dead_letters_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<DeadLetter>, Error> {
+        let mut letters = self
+            .connection
+            .query(
+                "SELECT id, received_at, payload, error, topic, kafka_partition, kafka_offset, delivery_tag FROM dead_letters WHERE day IN ? AND received_at >= ? AND received_at <= ?;",
+                (days(from, to), from, to),
+            )
+            .await
+            .map_err(|error| query_error(error, "while getting dead letters"))?
+            .rows_typed_or_empty::<DeadLetter>()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| rows_error(error, "while reading dead letters"))?;
+
+        // Days are read together, without order between them.
+        letters.sort_by_key(|letter| Reverse(letter.received_at));
+
+        Ok(letters)
+    }
+
+    /// Delete a message from `dead_letters` table.
+    pub async fn delete_dead_letter(
+        &self,
+        letter: &DeadLetter,
+    ) -> Result<(), Error> {
+        self.connection
+            .query(
+                "DELETE FROM dead_letters WHERE day = ? AND received_at = ? AND id = ?;",
+                (letter.received_at.date_naive(), letter.received_at, letter.id),
+            )
+            .await
+            .map_err(|error| {
+                query_error(error, "while deleting a dead letter")
+            })?;
+
+        Ok(())
+    }
+
+    /// Count reports made against `target` per reason code.
+    ///
+    /// Reasons are read by pages, so reports are not all loaded at once.
+    pub async fn count_reports(
+        &self,
+        target: &str,
+    ) -> Result<BTreeMap<i32, usize>, Error> {
+        let mut counts = BTreeMap::new();
+        let mut paging_state: Option<Bytes> = None;
+
+        loop {
+            let result = self
+                .connection
+                .query_paged(
+                    Query::new("SELECT reason FROM reports WHERE target = ?;")
+                        .with_page_size(COUNT_PAGE_SIZE),
+                    (target,),
+                    paging_state,
+                )
+                .await
+                .map_err(|error| {
+                    query_error(
+                        error,
+                        "while counting reports against a target",
+                    )
+                })?;
+            paging_state = result.paging_state.clone();
+
+            for row in result.rows_typed_or_empty::<(i32,)>() {
+                let (reason,) = row.map_err(|error| {
+                    rows_error(error, "while reading report reasons")
+                })?;
+                *counts.entry(reason).or_insert(0) += 1;
+            }
+
+            if paging_state.is_none() {
+                return Ok(counts);
+            }
+        }
+    }
+
+    /// Get every sanction taken against `target`.
+    pub async fn sanctions_against(
+        &self,
+        target: &str,
+    ) -> Result<Vec<Sanction>, Error> {
+        self.connection
+            .query(
+                "SELECT id, date, source, target, reason_code, text_reason, sanction, expires_at, status FROM sanctions WHERE target = ?;",
+                (target,),
+            )
+            .await
+            .map_err(|error| {
+                query_error(error, "while getting sanctions against a target")
+            })?
+            .rows_typed_or_empty::<Sanction>()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| rows_error(error, "while reading sanctions"))
+    }
+
+    /// Get reports made against `target` between `from` and `to`
+    /// included, most recent first.
+    ///
+    /// Reports are partitioned by day, every day of the range is read by a
+    /// single query.
+    pub async fn reports_between(
+        &self,
+        target: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Report>, Error> {
+        let mut reports = self
+            .connection
+            .query(
+                "SELECT id, day, time, source, target, reason, text_reason FROM reports_by_target WHERE target = ? AND day IN ? AND time >= ? AND time <= ?;",
+                (target, days(from, to), from, to),
+            )
+            .await
+            .map_err(|error| {
+                query_error(error, "while getting reports in a time range")
+            })?
+            .rows_typed_or_empty::<Report>()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| rows_error(error, "while reading reports"))?;
+
+        // Days are read together, without order between them.
+        reports.sort_by_key(|report| Reverse(report.time));
+
+        Ok(reports)
+    }
+
+    /// Get a page of reports made against `target`.
+    pub async fn reports_page(
+        &self,
+        target: &str,
+        page_size: i32,
+        paging_state: Option<Vec<u8>>,
+    ) -> Result<Page<Report>, Error> {
+        self.select_page(
+            "SELECT id, date, time, source, target, reason, text_reason FROM reports WHERE target = ?;",
+            (target,),
+            page_size,
+            paging_state,
+            "while getting a page of reports",
+        )
+        .await
+    }
+
+    /// Get a page of sanctions taken against `target`.
+    pub async fn sanctions_page(
+        &self,
+        target: &str,
+        page_size: i32,
+        paging_state: Option<Vec<u8>>,
+    ) -> Result<Page<Sanction>, Error> {
+        self.select_page(
+            "SELECT id, date, source, target, reason_code, text_reason, sanction, expires_at, status FROM sanctions WHERE target = ?;",
+            (target,),
+            page_size,
+            paging_state,
+            "while getting a page of sanctions",
+        )
+        .await
+    }
+
+    /// Get a report by its identifier.
+    pub async fn report(&self, id: Uuid) -> Result<Option<Report>, Error> {
+        self.connection
+            .query(
+                "SELECT id, date, time, source, target, reason, text_reason FROM reports WHERE id = ?;",
+                (id,),
+            )
+            .await
+            .map_err(|error| query_error(error, "while getting a report"))?
+            .maybe_first_row_typed::<Report>()
+            .map_err(|error| rows_error(error, "while reading a report"))
+    }
+
+    /// Get a sanction by its identifier.
+    pub async fn sanction(&self, id: Uuid) -> Result<Option<Sanction>, Error> {
+        self.connection
+            .query(
+                "SELECT id, date, source, target, reason_code, text_reason, sanction, expires_at, status FROM sanctions WHERE id = ?;",
+                (id,),
+            )
+            .await
+            .map_err(|error| query_error(error, "while getting a sanction"))?
+            .maybe_first_row_typed::<Sanction>()
+            .map_err(|error| rows_error(error, "while reading a sanction"))
+    }
+
+    /// Get ends of sanctions passed at `to`, filed from `from`, in
+    /// `sanctions_by_end` table.
+    pub async fn sanctions_ending(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<SanctionEnd>, Error> {
+        self.connection
+            .query(
+                "SELECT day, expires_at, id FROM sanctions_by_end WHERE day IN ? AND expires_at <= ?;",
+                (days(from, to), to),
+            )
+            .await
+            .map_err(|error| {
+                query_error(error, "while getting ending sanctions")
+            })?
+            .rows_typed_or_empty::<SanctionEnd>()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| rows_error(error, "while reading sanction ends"))
+    }
+
+    /// Delete a handled end of sanction from `sanctions_by_end` table.
+    pub async fn delete_sanction_end(
+        &self,
+        end: &SanctionEnd,
+    ) -> Result<(), Error> {
+        self.connection
+            .query(
+                "DELETE FROM sanctions_by_end WHERE day = ? AND expires_at = ? AND id = ?;",
+                (end.day, end.expires_at, end.id),
+            )
+            .await
+            .map_err(|error| {
+                query_error(error, "while deleting a sanction end")
+            })?;
+
+        Ok(())
+    }
+
+    /// Save an announcement before changing the status of a sanction.
+    pub async fn insert_announcement(
+        &self,
+        announcement: &PendingAnnouncement,
+    ) -> Result<(), Error> {
+        self.connection
+            .query(
+                "INSERT INTO pending_announcements (day, created_at, sanction_id, status) VALUES (?, ?, ?, ?);",
+                (
+                    announcement.day,
+                    announcement.created_at,
+                    announcement.sanction_id,
+                    announcement.status,
+                ),
+            )
+            .await
+            .map_err(|error| {
+                query_error(error, "while saving an announcement")
+            })?;
+
+        Ok(())
+    }
+
+    /// Get announcements made between `from` and `to` which are not sent
+    /// yet.
+    pub async fn pending_announcements(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<PendingAnnouncement>, Error> {
+        self.connection
+            .query(
+                "SELECT day, created_at, sanction_id, status FROM pending_announcements WHERE day IN ? AND created_at >= ? AND created_at <= ?;",
+                (days(from, to), from, to),
+            )
+            .await
+            .map_err(|error| {
+                query_error(error, "while getting pending announcements")
+            })?
+            .rows_typed_or_empty::<PendingAnnouncement>()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| rows_error(error, "while reading announcements"))
+    }
+
+    /// Delete a sent or outdated announcement.
+    pub async fn delete_announcement(
+        &self,
+        announcement: &PendingAnnouncement,
+    ) -> Result<(), Error> {
+        self.connection
+            .query(
+                "DELETE FROM pending_announcements WHERE day = ? AND created_at = ? AND sanction_id = ? AND status = ?;",
+                (
+                    announcement.day,
+                    announcement.created_at,
+                    announcement.sanction_id,
+                    announcement.status,
+                ),
+            )
+            .await
+            .map_err(|error| {
+                query_error(error, "while deleting an announcement")
+            })?;
+
+        Ok(())
+    }
+
+    /// Change the status of a sanction, only if it is still `expected`.
+    ///
+    /// Returns whether the status has been changed.
+    pub async fn update_sanction_status(
+        &self,
+        id: Uuid,
+        expected: Option<i32>,
+        status: i32,
+    ) -> Result<bool, Error> {
+        let result = self
+            .connection
+            .query(
+                "UPDATE sanctions SET status = ? WHERE id = ? IF status = ?;",
+                (status, id, expected),
+            )
+            .await
+            .map_err(|error| {
+                query_error(error, "while updating a sanction status")
+            })?;
+
+        applied(result)
+    }
+
+    /// Record that the event `id` sent by `source` has been processed.
+    ///
+    /// Records expire after seven days.
+    pub async fn mark_event_processed(
+        &self,
+        source: &str,
+        id: &str,
+    ) -> Result<(), Error> {
+        self.connection
+            .query(
+                "INSERT INTO processed_events (source, id, received_at) VALUES (?, ?, ?);",
+                (source, id, Utc::now()),
+            )
+            .await
+            .map_err(|error| {
+                query_error(error, "while marking an event as processed")
+            })?;
+
+        Ok(())
+    }
+
+    /// Check whether the event `id` sent by `source` has been recorded by
+    /// [`Manager::mark_event_processed`].
+    pub async fn is_event_processed(
+        &self,
+        source: &str,
+        id: &str,
+    ) -> Result<bool, Error> {
+        let result = self
+            .connection
+            .query(
+                "SELECT id FROM processed_events WHERE source = ? AND id = ?;",
+                (source, id),
+            )
+            .await
+            .map_err(|error| {
+                query_error(error, "while checking a processed event")
+            })?;
+
+        Ok(result.rows_num().unwrap_or_default() > 0)
+    }
+
+    /// Record that the message `id` has been delivered to `broker`.
+    ///
+    /// Records expire after seven days.
+    pub async fn mark_delivered(
+        &self,
+        id: Uuid,
+        broker: &str,
+    ) -> Result<(), Error> {
+        self.connection
+            .query(
+                "INSERT INTO deliveries (message_id, broker, delivered_at) VALUES (?, ?, ?);",
+                (id, broker, Utc::now()),
+            )
+            .await
+            .map_err(|error| query_error(error, "while recording a delivery"))?;
+
+        Ok(())
+    }
+
+    /// Get the brokers the message `id` has been delivered to.
+    pub async fn deliveries(&self, id: Uuid) -> Result<Vec<String>, Error> {
+        self.connection
+            .query("SELECT broker FROM deliveries WHERE message_id = ?;", (id,))
+            .await
+            .map_err(|error| query_error(error, "while getting deliveries"))?
+            .rows_typed_or_empty::<(String,)>()
+            .map(|row| row.map(|(broker,)| broker))
+            .collect::<Result<_, _>>()
+            .map_err(|error| rows_error(error, "while reading deliveries"))
+    }
+
+    async fn select_page<T: FromRow>(
+        &self,
+        query: &str,
+        values: impl SerializeRow,
+        page_size: i32,
+        paging_state: Option<Vec<u8>>,
+        context: &str,
+    ) -> Result<Page<T>, Error> {
+        let result = self
+            .connection
+            .query_paged(
+                Query::new(query).with_page_size(page_size),
+                values,
+                paging_state.map(Bytes::from),
+            )
+            .await
+            .map_err(|error| query_error(error, context))?;
+        let paging_state =
+            result.paging_state.as_ref().map(|state| state.to_vec());
+
+        Ok(Page {
+            rows: result
+                .rows_typed_or_empty::<T>()
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|error| rows_error(error, context))?,
+            paging_state,
+        })
+    }
+}
+
+impl Storage for Manager {
+    async fn insert_report(&self, report: &Report) -> Result<(), Error> {
+        Manager::insert_report(self, report).await
+    }
+
+    async fn insert_sanction(&self, sanction: &Sanction) -> Result<(), Error> {
+        Manager::insert_sanction(self, sanction).await
+    }
+
+    async fn insert_dead_letter(
+        &self,
+        letter: &DeadLetter,
+    ) -> Result<(), Error> {
+        Manager::insert_dead_letter(self, letter).await
+    }
+
+    async fn reports_between(
+        &self,
+        target: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Report>, Error> {
+        Manager::reports_between(self, target, from, to).await
+    }
+
+    async fn sanctions_against(
+        &self,
+        target: &str,
+    ) -> Result<Vec<Sanction>, Error> {
+        Manager::sanctions_against(self, target).await
+    }
+
+    async fn mark_event_processed(
+        &self,
+        source: &str,
+        id: &str,
+    ) -> Result<(), Error> {
+        Manager::mark_event_processed(self, source, id).await
+    }
+
+    async fn is_event_processed(
+        &self,
+        source: &str,
+        id: &str,
+    ) -> Result<bool, Error> {
+        Manager::is_event_processed(self, source, id).await
+    }
+
+    async fn mark_delivered(
+        &self,
+        id: Uuid,
+        broker: &str,
+    ) -> Result<(), Error> {
+        Manager::mark_delivered(self, id, broker).await
+    }
+
+    async fn deliveries(&self, id: Uuid) -> Result<Vec<String>, Error> {
+        Manager::deliveries(self, id).await
+    }
+}
+
+/// Map a failure to open a session into an [`Error`].
+fn session_error(error: NewSessionError) -> Error {
+    Error::new(
+        ErrorType::Database(PoolCreation),
+        Some(Box::new(error)),
+        Some("while connecting to Apache Cassandra".to_string()),
+    )
+}
+
+/// Every day between `from` and `to` included, at most the last
+/// [`MAX_DAYS`] ones.
+///
+/// Rows partitioned by day are read over 31 days at most, so a far `from`
+/// must not expand into an `IN` list Cassandra rejects.
+fn days(from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<NaiveDate> {
+    let to = to.date_naive();
+    let from = from
+        .date_naive()
+        .max(to - chrono::Days::new(MAX_DAYS as u64 - 1));
+
+    from.iter_days().take_while(|day| *day <= to).collect()
+}
+
+/// Day an end of sanction is filed under in `sanctions_by_end` table.
+///
+/// Ends already passed are filed under today, so they are found by the next
+/// check.
+fn end_day(expires_at: DateTime<Utc>) -> NaiveDate {
+    expires_at.max(Utc::now()).date_naive()
+}
+
+/// Whether a lightweight transaction has been applied.
+fn applied(result: QueryResult) -> Result<bool, Error> {
+    let row = result.first_row().map_err(|error| {
+        rows_error(error, "while reading a lightweight transaction")
+    })?;
+
+    Ok(matches!(
+        row.columns.first(),
+        Some(Some(CqlValue::Boolean(true)))
+    ))
+}
+
+/// Wrap a [`QueryError`] into an [`Error`].
+fn query_error(error: QueryError, context: &str) -> Error {
+    Error::new(
+        ErrorType::Database(QueryFailed),
+        Some(Box::new(error)),
+        Some(context.to_string()),
+    )
+}
+
+/// Wrap an error raised while reading rows into an [`Error`].
+fn rows_error(
+    error: impl std::error::Error + Send + Sync + 'static,
+    context: &str,
+) -> Error {
+    Error::new(
+        ErrorType::Database(InvalidRows),
+        Some(Box::new(error)),
+        Some(context.to_string()),
+    )
+}
@@ -1,81 +1,133 @@
-//! Manage Apache Kafka message broker pool of connections.
+//! Manage Apache Kafka producers and consumers.
 
-mod pool;
+mod sasl;
+mod subscriber;
+mod tls;
 
-pub use kafka::consumer::Consumer;
-use kafka::{
-    consumer::{FetchOffset, GroupOffsetStorage},
-    producer::Record,
-    Error as KafkaError,
+use crate::broker::{Message, Publisher};
+pub use rdkafka::consumer::StreamConsumer as Consumer;
+use rdkafka::{
+    config::ClientConfig,
+    consumer::Consumer as _,
+    producer::{FutureProducer, FutureRecord, Producer as _},
+    util::Timeout,
 };
-use pool::KafkaConnectionManager;
+pub use sasl::{SaslConfig, SaslMechanism};
 use signaly_error::{
-    DatabaseError::{PoolCreation, PoolObtention},
+    DatabaseError::{MessageNotSent, PoolCreation},
     Error, ErrorType,
 };
+pub use subscriber::KafkaSubscriber;
 
-type Pool = deadpool::managed::Pool<KafkaConnectionManager>;
+use std::time::Duration;
 
-/// Manage Apache Kafka pool connection.
-#[allow(dead_code, missing_debug_implementations)]
+pub use tls::TlsConfig;
+
+/// Default consumer group.
+pub const DEFAULT_GROUP: &str = "signaly";
+
+/// Time brokers have to answer a metadata request.
+const METADATA_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Manage Apache Kafka producer.
+///
+/// A single producer multiplexes messages over one connection per broker.
+#[allow(missing_debug_implementations)]
 pub struct Manager {
-    /// Pool session.
-    pub session: Pool,
+    producer: FutureProducer,
+    config: ProducerConfig,
 }
 
 impl Manager {
-    /// Create a new pool of producer connections.
+    /// Create a new producer.
     ///
     /// # Examples
     /// ```rust
-    /// use signaly_db::kafka::Manager as KafkaManger;
+    /// use signaly_db::kafka::{Manager as KafkaManger, ProducerConfig};
     ///
     /// let session = KafkaManger::new(
     ///     vec!["localhost:9092".to_string()],
-    ///     10,
+    ///     ProducerConfig::default(),
     /// );
     /// // Do what ever you want with your cool new session...
     /// ```
     pub async fn new(
         urls: Vec<String>,
-        pool_size: usize,
+        config: ProducerConfig,
     ) -> Result<Self, Error> {
-        Ok(Manager {
-            session: Pool::builder(KafkaConnectionManager::new(urls))
-                .max_size(pool_size)
-                .build()
+        let producer =
+            client_config(&urls, config.tls.as_ref(), config.sasl.as_ref())
+                .set("acks", config.required_acks.as_str())
+                .set("request.timeout.ms", millis(config.ack_timeout))
+                .set("message.timeout.ms", millis(config.delivery_timeout()))
+                .set("compression.type", config.compression.as_str())
+                .set("retries", config.retries.to_string())
+                .set("retry.backoff.ms", millis(config.retry_backoff))
+                .set(
+                    "retry.backoff.max.ms",
+                    millis(config.retry_backoff.max(Duration::from_secs(1))),
+                )
+                .create::<FutureProducer>()
                 .map_err(|error| {
                     Error::new(
                         ErrorType::Database(PoolCreation),
                         Some(Box::new(error)),
-                        None,
+                        Some("while creating a Kafka producer".to_string()),
                     )
-                })?,
+                })?;
+
+        Ok(Manager { producer, config })
+    }
+
+    /// Check that brokers answer a metadata request, which writes nothing.
+    pub async fn check_health(&self) -> bool {
+        let producer = self.producer.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            producer
+                .client()
+                .fetch_metadata(None, METADATA_TIMEOUT)
+                .map(|_| ())
+                .map_err(|error| error.to_string())
         })
+        .await
+        .unwrap_or_else(|error| Err(error.to_string()));
+
+        match result {
+            Ok(()) => true,
+            Err(error) => {
+                tracing::error!(
+                    target: "signaly-db.kafka",
+                    error = error,
+                    "Brokers did not answer a metadata request."
+                );
+                false
+            },
+        }
     }
 
     /// Send a message via Kafka broker.
+    ///
+    /// Messages with the same `key` are sent to the same partition, so they
+    /// are consumed in order, unless `partition_by_key` is disabled.
+    /// Failed sends are retried `retries` times by the producer.
     pub async fn send(
         &self,
         topic: String,
+        key: Option<&str>,
         content: String,
     ) -> Result<(), Error> {
-        self.session
-            .get()
+        let mut record = FutureRecord::<str, str>::to(&topic).payload(&content);
+        if let Some(key) = key.filter(|_| self.config.partition_by_key) {
+            record = record.key(key);
+        }
+
+        self.producer
+            .send(record, Timeout::Never)
             .await
-            .map_err(|error| {
-                Error::new(
-                    ErrorType::Database(PoolObtention),
-                    Some(Box::new(error)),
-                    Some(
-                        "while trying to send a message via Kafka".to_string(),
-                    ),
-                )
-            })?
-            .send(&Record::from_value(topic.as_str(), content))
-            .map_err(|error| {
+            .map(|_| ())
+            .map_err(|(error, _)| {
                 Error::new(
-                    ErrorType::Unspecified,
+                    ErrorType::Database(MessageNotSent),
                     Some(Box::new(error)),
                     Some(
                         "while trying to send a message via Kafka".to_string(),
@@ -85,15 +137,245 @@ impl Manager {
     }
 }
 
-/// Create a consumer connection.
+impl Publisher for Manager {
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    async fn publish(
+        &self,
+        topic: &str,
+        message: Message<'_>,
+    ) -> Result<(), Error> {
+        self.send(topic.to_string(), message.key, message.content)
+            .await
+    }
+
+    async fn check_health(&self) -> bool {
+        Manager::check_health(self).await
+    }
+}
+
+/// Acknowledgements required for a message to be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequiredAcks {
+    /// Do not wait for brokers.
+    None,
+    /// Wait for the partition leader.
+    One,
+    /// Wait for every in-sync replica.
+    All,
+}
+
+impl RequiredAcks {
+    fn as_str(self) -> &'static str {
+        match self {
+            RequiredAcks::None => "0",
+            RequiredAcks::One => "1",
+            RequiredAcks::All => "all",
+        }
+    }
+}
+
+/// Compression of messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// No compression.
+    None,
+    /// Gzip compression.
+    Gzip,
+    /// Snappy compression.
+    Snappy,
+}
+
+impl Compression {
+    fn as_str(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Snappy => "snappy",
+        }
+    }
+}
+
+/// Where a consumer starts when its group has no committed offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchOffset {
+    /// Oldest available message.
+    Earliest,
+    /// Next produced message.
+    Latest,
+}
+
+impl FetchOffset {
+    fn as_str(self) -> &'static str {
+        match self {
+            FetchOffset::Earliest => "earliest",
+            FetchOffset::Latest => "latest",
+        }
+    }
+}
+
+/// Configuration of producers.
+#[derive(Debug, Clone)]
+pub struct ProducerConfig {
+    /// Acknowledgements required for a message to be sent.
+    pub required_acks: RequiredAcks,
+    /// Time brokers wait for `required_acks`.
+    pub ack_timeout: Duration,
+    /// Compression of messages.
+    pub compression: Compression,
+    /// Send messages with the same key to the same partition.
+    pub partition_by_key: bool,
+    /// Number of times a failed send is retried.
+    pub retries: u32,
+    /// Delay before the first retry, increased at each retry.
+    pub retry_backoff: Duration,
+    /// Connect to brokers with TLS.
+    pub tls: Option<TlsConfig>,
+    /// Authenticate to brokers with SASL.
+    pub sasl: Option<SaslConfig>,
+}
+
+impl ProducerConfig {
+    /// Time a message has to be sent, retries included.
+    fn delivery_timeout(&self) -> Duration {
+        (self.ack_timeout + self.retry_backoff) * (self.retries + 1)
+    }
+}
+
+impl Default for ProducerConfig {
+    fn default() -> Self {
+        ProducerConfig {
+            required_acks: RequiredAcks::All,
+            ack_timeout: Duration::from_secs(1),
+            compression: Compression::Gzip,
+            partition_by_key: true,
+            retries: 3,
+            retry_backoff: Duration::from_millis(100),
+            tls: None,
+            sasl: None,
+        }
+    }
+}
+
+/// Configuration of a consumer.
+#[derive(Debug, Clone)]
+pub struct ConsumerConfig {
+    /// Consumer group. Consumers of a group share partitions and committed
+    /// offsets, so distinct deployments must use distinct groups.
+    pub group: String,
+    /// Consumed topics.
+    pub topics: Vec<String>,
+    /// Where to start when the group has no committed offset for a
+    /// partition.
+    pub fallback_offset: FetchOffset,
+    /// Minimum number of bytes the broker waits for before answering.
+    pub fetch_min_bytes: i32,
+    /// Maximum number of bytes fetched per partition.
+    pub fetch_max_bytes_per_partition: i32,
+    /// Maximum time the broker waits for `fetch_min_bytes`.
+    pub fetch_max_wait_time: Duration,
+    /// Connect to brokers with TLS.
+    pub tls: Option<TlsConfig>,
+    /// Authenticate to brokers with SASL.
+    pub sasl: Option<SaslConfig>,
+}
+
+impl Default for ConsumerConfig {
+    fn default() -> Self {
+        ConsumerConfig {
+            group: DEFAULT_GROUP.to_string(),
+            topics: Vec::new(),
+            fallback_offset: FetchOffset::Earliest,
+            fetch_min_bytes: 4096,
+            fetch_max_bytes_per_partition: 32768,
+            fetch_max_wait_time: Duration::from_millis(100),
+            tls: None,
+            sasl: None,
+        }
+    }
+}
+
+/// Create a consumer connection, checking that brokers answer.
+///
+/// Offsets are committed in Kafka for `config.group`, once messages are
+/// acknowledged by [`KafkaSubscriber`].
 pub async fn new_consumer(
-    topic: String,
     urls: Vec<String>,
-) -> Result<Consumer, KafkaError> {
-    Consumer::from_hosts(urls)
-        .with_topic(topic)
-        .with_group("my-group".to_string())
-        .with_fallback_offset(FetchOffset::Earliest)
-        .with_offset_storage(Some(GroupOffsetStorage::Kafka))
-        .create()
+    config: ConsumerConfig,
+) -> Result<Consumer, Error> {
+    let error = |error, context: &str| {
+        Error::new(
+            ErrorType::Unspecified,
+            Some(Box::new(error)),
+            Some(context.to_string()),
+        )
+    };
+
+    let consumer =
+        client_config(&urls, config.tls.as_ref(), config.sasl.as_ref())
+            .set("group.id", &config.group)
+            .set("auto.offset.reset", config.fallback_offset.as_str())
+            // Only offsets of acknowledged messages are committed.
+            .set("enable.auto.offset.store", "false")
+            .set("fetch.min.bytes", config.fetch_min_bytes.to_string())
+            .set(
+                "max.partition.fetch.bytes",
+                config.fetch_max_bytes_per_partition.to_string(),
+            )
+            .set("fetch.wait.max.ms", millis(config.fetch_max_wait_time))
+            .create::<Consumer>()
+            .map_err(|err| error(err, "while creating a Kafka consumer"))?;
+
+    let topics: Vec<&str> = config.topics.iter().map(String::as_str).collect();
+    consumer
+        .subscribe(&topics)
+        .map_err(|err| error(err, "while subscribing to Kafka topics"))?;
+
+    tokio::task::spawn_blocking(move || {
+        consumer
+            .fetch_metadata(None, METADATA_TIMEOUT)
+            .map(|_| consumer)
+            .map_err(|err| error(err, "while connecting to Kafka brokers"))
+    })
+    .await
+    .map_err(|err| {
+        Error::new(
+            ErrorType::Unspecified,
+            Some(Box::new(err)),
+            Some("while connecting to Kafka brokers".to_string()),
+        )
+    })?
+}
+
+/// Settings shared by producers and consumers.
+fn client_config(
+    urls: &[String],
+    tls: Option<&TlsConfig>,
+    sasl: Option<&SaslConfig>,
+) -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config.set("bootstrap.servers", urls.join(",")).set(
+        "security.protocol",
+        match (tls.is_some(), sasl.is_some()) {
+            (false, false) => "plaintext",
+            (true, false) => "ssl",
+            (false, true) => "sasl_plaintext",
+            (true, true) => "sasl_ssl",
+        },
+    );
+
+    if let Some(tls) = tls {
+        tls.apply(&mut config);
+    }
+    if let Some(sasl) = sasl {
+        sasl.apply(&mut config);
+    }
+
+    config
+}
+
+fn millis(duration: Duration) -> String {
+    duration.as_millis().to_string()
 }
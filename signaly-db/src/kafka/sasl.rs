@@ -0,0 +1,57 @@
+//! SASL authentication to Kafka brokers.
+
+use std::fmt;
+
+use rdkafka::config::ClientConfig;
+
+/// SASL mechanisms supported by brokers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaslMechanism {
+    /// Clear text password, to be used with TLS only.
+    Plain,
+    /// Salted challenge with SHA-256.
+    ScramSha256,
+    /// Salted challenge with SHA-512.
+    ScramSha512,
+}
+
+impl SaslMechanism {
+    /// Name of the mechanism, such as `SCRAM-SHA-512`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+            SaslMechanism::ScramSha512 => "SCRAM-SHA-512",
+        }
+    }
+}
+
+/// SASL credentials of connections to brokers.
+#[derive(Clone)]
+pub struct SaslConfig {
+    /// Authentication mechanism.
+    pub mechanism: SaslMechanism,
+    /// User name.
+    pub username: String,
+    /// Password of `username`.
+    pub password: String,
+}
+
+impl fmt::Debug for SaslConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SaslConfig")
+            .field("mechanism", &self.mechanism)
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SaslConfig {
+    /// Set the credentials of a client.
+    pub(super) fn apply(&self, config: &mut ClientConfig) {
+        config
+            .set("sasl.mechanism", self.mechanism.as_str())
+            .set("sasl.username", &self.username)
+            .set("sasl.password", &self.password);
+    }
+}
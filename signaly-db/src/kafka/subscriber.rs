@@ -0,0 +1,141 @@
+//! Receive messages from Kafka.
+
+use std::{sync::Arc, time::Duration};
+
+use rdkafka::{
+    consumer::{CommitMode, Consumer as _},
+    error::KafkaError,
+    message::{BorrowedMessage, Message},
+    types::RDKafkaErrorCode,
+};
+use signaly_error::{Error, ErrorType};
+
+use super::Consumer;
+use crate::broker::{Acknowledgement, Delivery, Origin, Subscriber};
+
+/// Maximum number of messages received at once.
+const MAX_DELIVERIES: usize = 500;
+
+/// Kafka consumer.
+///
+/// Offsets of acknowledged messages are committed every few seconds, and
+/// when the subscriber is closed.
+#[allow(missing_debug_implementations)]
+pub struct KafkaSubscriber {
+    consumer: Arc<Consumer>,
+}
+
+impl KafkaSubscriber {
+    /// Receive the messages of `consumer`.
+    pub fn new(consumer: Consumer) -> Self {
+        KafkaSubscriber {
+            consumer: Arc::new(consumer),
+        }
+    }
+}
+
+impl Subscriber for KafkaSubscriber {
+    /// Kafka cannot deliver a message again without stopping its
+    /// partition.
+    const REQUEUES: bool = false;
+
+    fn name(&self) -> &'static str {
+        "kafka"
+    }
+
+    async fn receive(&mut self) -> Result<Vec<Delivery>, Error> {
+        let message = self.consumer.recv().await.map_err(|err| {
+            error(Some(Box::new(err)), "while polling Kafka".to_string())
+        })?;
+        let mut deliveries = vec![delivery(&message)];
+        drop(message);
+
+        // Take the messages already fetched, without waiting for more.
+        while deliveries.len() < MAX_DELIVERIES {
+            match tokio::time::timeout(Duration::ZERO, self.consumer.recv())
+                .await
+            {
+                Ok(Ok(message)) => deliveries.push(delivery(&message)),
+                Ok(Err(err)) => {
+                    tracing::warn!(
+                        target: "signaly-db.kafka",
+                        error = err.to_string(),
+                        "Kafka consumer reported an error."
+                    );
+                    break;
+                },
+                Err(_) => break,
+            }
+        }
+
+        Ok(deliveries)
+    }
+
+    async fn acknowledge(
+        &mut self,
+        origin: &Origin,
+        acknowledgement: Acknowledgement,
+    ) -> Result<(), Error> {
+        let (Acknowledgement::Ack, Some(partition), Some(offset)) =
+            (acknowledgement, origin.partition, origin.offset)
+        else {
+            return Ok(());
+        };
+
+        // The next offset of the partition is stored, to be committed.
+        self.consumer
+            .store_offset(&origin.topic, partition, offset)
+            .map_err(|err| {
+                error(
+                    Some(Box::new(err)),
+                    "while marking a Kafka message as consumed".to_string(),
+                )
+            })
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        let consumer = Arc::clone(&self.consumer);
+
+        tokio::task::spawn_blocking(move || {
+            match consumer.commit_consumer_state(CommitMode::Sync) {
+                // Nothing was acknowledged since the last commit.
+                Err(KafkaError::ConsumerCommit(RDKafkaErrorCode::NoOffset)) => {
+                    Ok(())
+                },
+                result => result,
+            }
+            .map_err(|err| {
+                error(
+                    Some(Box::new(err)),
+                    "while committing Kafka offsets".to_string(),
+                )
+            })
+        })
+        .await
+        .map_err(|err| {
+            error(
+                Some(Box::new(err)),
+                "while committing Kafka offsets".to_string(),
+            )
+        })?
+    }
+}
+
+fn delivery(message: &BorrowedMessage<'_>) -> Delivery {
+    Delivery {
+        origin: Origin {
+            topic: message.topic().to_string(),
+            partition: Some(message.partition()),
+            offset: Some(message.offset()),
+            ..Default::default()
+        },
+        payload: message.payload().unwrap_or_default().to_vec(),
+    }
+}
+
+fn error(
+    cause: Option<Box<dyn std::error::Error + Send + Sync>>,
+    context: String,
+) -> Error {
+    Error::new(ErrorType::Unspecified, cause, Some(context))
+}
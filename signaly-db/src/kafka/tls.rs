@@ -0,0 +1,56 @@
+//! TLS connections to Kafka brokers.
+
+use std::path::PathBuf;
+
+use rdkafka::config::ClientConfig;
+
+/// TLS settings of connections to brokers.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM bundle of certificate authorities trusted instead of the system
+    /// ones.
+    pub ca_file: Option<PathBuf>,
+    /// PEM client certificate chain, sent to brokers requiring mutual TLS.
+    pub cert_file: Option<PathBuf>,
+    /// PEM private key of `cert_file`.
+    pub key_file: Option<PathBuf>,
+    /// Check that broker certificates are issued for the broker host.
+    pub verify_hostname: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            ca_file: None,
+            cert_file: None,
+            key_file: None,
+            verify_hostname: true,
+        }
+    }
+}
+
+impl TlsConfig {
+    /// Set certificates and keys of a client. They are loaded when the
+    /// client is created.
+    pub(super) fn apply(&self, config: &mut ClientConfig) {
+        for (key, path) in [
+            ("ssl.ca.location", &self.ca_file),
+            ("ssl.certificate.location", &self.cert_file),
+            ("ssl.key.location", &self.key_file),
+        ] {
+            if let Some(path) = path {
+                config.set(key, path.to_string_lossy());
+            }
+        }
+
+        // The certificate chain is verified either way.
+        config.set(
+            "ssl.endpoint.identification.algorithm",
+            if self.verify_hostname {
+                "https"
+            } else {
+                "none"
+            },
+        );
+    }
+}
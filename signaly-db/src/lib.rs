@@ -11,18 +11,25 @@
 //! Supported databases:
 //! - Apache Cassandra;
 //! - ScyllaDB;
-//! - InfluxDB.
+//! - InfluxDB;
+//! - memory, with the `memory` feature.
 //!
 //! Supported brokers:
 //! - Apache Kafka;
 //! - Redpanda;
-//! - RabbitMQ.
+//! - RabbitMQ;
+//! - memory, with the `memory` feature.
 
+pub mod broker;
 #[cfg(feature = "cassandra")]
 pub mod cassandra;
 #[cfg(feature = "timeseries")]
 pub mod influxdb;
 #[cfg(feature = "apache_kafka")]
 pub mod kafka;
+#[cfg(feature = "memory")]
+pub mod memory;
 #[cfg(feature = "rabbitmq")]
 pub mod rabbitmq;
+#[cfg(any(feature = "cassandra", feature = "memory"))]
+pub mod storage;
@@ -0,0 +1,239 @@
+//! In-memory message broker.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use signaly_error::{DatabaseError::MessageNotSent, Error, ErrorType};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::broker::{
+    Acknowledgement, Delivery, Message, Origin, Publisher, Subscriber,
+};
+
+/// Message published on a [`MemoryBroker`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Published {
+    /// Key of the message.
+    pub key: Option<String>,
+    /// Routing key of the message.
+    pub routing_key: String,
+    /// Content of the message.
+    pub content: String,
+}
+
+#[derive(Debug, Default)]
+struct Topic {
+    /// Every message published, in order.
+    messages: Vec<Published>,
+    /// Senders of the subscribers of the topic.
+    subscribers: Vec<UnboundedSender<Delivery>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    topics: HashMap<String, Topic>,
+    /// Acknowledgements of subscribers, in order.
+    acknowledgements: Vec<(Origin, Acknowledgement)>,
+    /// Whether publishing fails, as if the broker was down.
+    unavailable: bool,
+}
+
+/// Broker keeping messages in memory.
+///
+/// Messages published on a topic are delivered to every subscriber of the
+/// topic, and kept so they can be inspected.
+#[derive(Debug, Clone)]
+pub struct MemoryBroker {
+    name: &'static str,
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for MemoryBroker {
+    fn default() -> Self {
+        MemoryBroker::named("memory")
+    }
+}
+
+impl MemoryBroker {
+    /// Create an empty broker.
+    pub fn new() -> Self {
+        MemoryBroker::default()
+    }
+
+    /// Create an empty broker named `name`, to tell several brokers apart.
+    pub fn named(name: &'static str) -> Self {
+        MemoryBroker {
+            name,
+            state: Arc::default(),
+        }
+    }
+
+    /// Receive messages published on `topic` from now on.
+    pub fn subscribe(&self, topic: &str) -> MemorySubscriber {
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        self.state()
+            .topics
+            .entry(topic.to_string())
+            .or_default()
+            .subscribers
+            .push(sender.clone());
+
+        MemorySubscriber {
+            broker: self.clone(),
+            sender,
+            receiver,
+        }
+    }
+
+    /// Publish `content` on `topic` without key.
+    pub fn push(&self, topic: &str, content: impl Into<String>) {
+        self.deliver(
+            topic,
+            Published {
+                key: None,
+                routing_key: topic.to_string(),
+                content: content.into(),
+            },
+        );
+    }
+
+    /// Messages published on `topic`, in order.
+    pub fn published(&self, topic: &str) -> Vec<Published> {
+        self.state()
+            .topics
+            .get(topic)
+            .map(|topic| topic.messages.clone())
+            .unwrap_or_default()
+    }
+
+    /// Make [`Publisher::publish`] fail until set back to `true`, as if the
+    /// broker was down. Subscribers still receive messages.
+    pub fn set_available(&self, available: bool) {
+        self.state().unavailable = !available;
+    }
+
+    /// Acknowledgements sent by subscribers, in order.
+    pub fn acknowledgements(&self) -> Vec<(Origin, Acknowledgement)> {
+        self.state().acknowledgements.clone()
+    }
+
+    fn deliver(&self, topic: &str, message: Published) {
+        let mut state = self.state();
+        let entry = state.topics.entry(topic.to_string()).or_default();
+
+        let delivery = Delivery {
+            origin: Origin {
+                topic: topic.to_string(),
+                offset: Some(entry.messages.len() as i64),
+                ..Default::default()
+            },
+            payload: message.content.clone().into_bytes(),
+        };
+
+        entry
+            .subscribers
+            .retain(|subscriber| subscriber.send(delivery.clone()).is_ok());
+        entry.messages.push(message);
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // State is always consistent, even if a holder panicked.
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Publisher for MemoryBroker {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    async fn publish(
+        &self,
+        topic: &str,
+        message: Message<'_>,
+    ) -> Result<(), Error> {
+        if self.state().unavailable {
+            return Err(Error::new(
+                ErrorType::Database(MessageNotSent),
+                None,
+                Some("memory broker is unavailable".to_string()),
+            ));
+        }
+
+        self.deliver(
+            topic,
+            Published {
+                key: message.key.map(ToString::to_string),
+                routing_key: message.routing_key,
+                content: message.content,
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn check_health(&self) -> bool {
+        !self.state().unavailable
+    }
+}
+
+/// Subscriber of a [`MemoryBroker`] topic.
+#[derive(Debug)]
+pub struct MemorySubscriber {
+    broker: MemoryBroker,
+    /// Sender used to deliver requeued messages again.
+    sender: UnboundedSender<Delivery>,
+    receiver: UnboundedReceiver<Delivery>,
+}
+
+impl Subscriber for MemorySubscriber {
+    /// Requeued messages are delivered again, rejected ones are dropped.
+    const REQUEUES: bool = true;
+
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn receive(&mut self) -> Result<Vec<Delivery>, Error> {
+        match self.receiver.recv().await {
+            Some(delivery) => Ok(vec![delivery]),
+            // The subscriber keeps a sender, so the channel is never
+            // closed.
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn acknowledge(
+        &mut self,
+        origin: &Origin,
+        acknowledgement: Acknowledgement,
+    ) -> Result<(), Error> {
+        let mut state = self.broker.state();
+        state
+            .acknowledgements
+            .push((origin.clone(), acknowledgement));
+
+        if acknowledgement == Acknowledgement::Requeue {
+            let content = state
+                .topics
+                .get(&origin.topic)
+                .zip(origin.offset)
+                .and_then(|(topic, offset)| topic.messages.get(offset as usize))
+                .map(|message| message.content.clone().into_bytes());
+
+            if let Some(payload) = content {
+                let _ = self.sender.send(Delivery {
+                    origin: origin.clone(),
+                    payload,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,8 @@
+//! In-memory message broker and storage, to run Signaly without any
+//! service, such as in tests.
+
+mod broker;
+mod storage;
+
+pub use broker::{MemoryBroker, MemorySubscriber, Published};
+pub use storage::MemoryStorage;
@@ -0,0 +1,170 @@
+//! In-memory storage.
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use chrono::{DateTime, NaiveTime, Utc};
+use signaly_error::Error;
+use uuid::Uuid;
+
+use crate::storage::{DeadLetter, Report, Sanction, Storage};
+
+#[derive(Debug, Default)]
+struct State {
+    reports: Vec<Report>,
+    sanctions: Vec<Sanction>,
+    dead_letters: Vec<DeadLetter>,
+    /// Source and identifier of processed events.
+    events: HashSet<(String, String)>,
+    /// Identifier of delivered messages and their broker.
+    deliveries: HashSet<(Uuid, String)>,
+}
+
+/// Storage keeping records in memory.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    state: Arc<Mutex<State>>,
+}
+
+impl MemoryStorage {
+    /// Create an empty storage.
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+
+    /// Every saved report, in order.
+    pub fn reports(&self) -> Vec<Report> {
+        self.state().reports.clone()
+    }
+
+    /// Every saved sanction, in order.
+    pub fn sanctions(&self) -> Vec<Sanction> {
+        self.state().sanctions.clone()
+    }
+
+    /// Every saved dead letter, in order.
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        self.state().dead_letters.clone()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // State is always consistent, even if a holder panicked.
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Storage for MemoryStorage {
+    async fn insert_report(&self, report: &Report) -> Result<(), Error> {
+        let reports = &mut self.state().reports;
+
+        // Like Cassandra, saving a report again replaces it.
+        reports.retain(|saved| saved.id != report.id);
+        reports.push(report.clone());
+
+        Ok(())
+    }
+
+    async fn insert_sanction(&self, sanction: &Sanction) -> Result<(), Error> {
+        let sanctions = &mut self.state().sanctions;
+
+        // Like Cassandra, saving a sanction again replaces it.
+        sanctions.retain(|saved| saved.id != sanction.id);
+        sanctions.push(sanction.clone());
+
+        Ok(())
+    }
+
+    async fn insert_dead_letter(
+        &self,
+        letter: &DeadLetter,
+    ) -> Result<(), Error> {
+        self.state().dead_letters.push(letter.clone());
+        Ok(())
+    }
+
+    async fn reports_between(
+        &self,
+        target: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Report>, Error> {
+        let mut reports: Vec<_> = self
+            .state()
+            .reports
+            .iter()
+            .filter(|report| report.target == target)
+            .map(|report| {
+                // Reports without time are saved at the beginning of their
+                // day.
+                let time = report.time.unwrap_or_else(|| {
+                    report.date.and_time(NaiveTime::MIN).and_utc()
+                });
+                (time, report)
+            })
+            .filter(|(time, _)| from <= *time && *time <= to)
+            .map(|(time, report)| (time, report.clone()))
+            .collect();
+
+        reports.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+        Ok(reports.into_iter().map(|(_, report)| report).collect())
+    }
+
+    async fn sanctions_against(
+        &self,
+        target: &str,
+    ) -> Result<Vec<Sanction>, Error> {
+        Ok(self
+            .state()
+            .sanctions
+            .iter()
+            .filter(|sanction| sanction.target == target)
+            .cloned()
+            .collect())
+    }
+
+    async fn mark_event_processed(
+        &self,
+        source: &str,
+        id: &str,
+    ) -> Result<(), Error> {
+        self.state()
+            .events
+            .insert((source.to_string(), id.to_string()));
+        Ok(())
+    }
+
+    async fn is_event_processed(
+        &self,
+        source: &str,
+        id: &str,
+    ) -> Result<bool, Error> {
+        Ok(self
+            .state()
+            .events
+            .contains(&(source.to_string(), id.to_string())))
+    }
+
+    async fn mark_delivered(
+        &self,
+        id: Uuid,
+        broker: &str,
+    ) -> Result<(), Error> {
+        self.state().deliveries.insert((id, broker.to_string()));
+        Ok(())
+    }
+
+    async fn deliveries(&self, id: Uuid) -> Result<Vec<String>, Error> {
+        Ok(self
+            .state()
+            .deliveries
+            .iter()
+            .filter(|(message, _)| *message == id)
+            .map(|(_, broker)| broker.clone())
+            .collect())
+    }
+}
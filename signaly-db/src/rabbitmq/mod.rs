@@ -1,27 +1,50 @@
 //! RabbitMQ message broker.
 
 mod pool;
+mod subscriber;
+mod topology;
 
-pub use ::lapin::{
-    options::{BasicAckOptions, BasicConsumeOptions},
-    types::FieldTable,
-};
+use crate::broker::{Message, Publisher};
+pub use ::lapin::ExchangeKind;
 use lapin::{
-    options::BasicPublishOptions, BasicProperties, ConnectionProperties,
+    options::{BasicConsumeOptions, BasicPublishOptions, BasicQosOptions},
+    publisher_confirm::Confirmation,
+    types::FieldTable,
+    BasicProperties, Channel, ConnectionProperties, Consumer,
 };
-use pool::LapinConnectionManager;
+use pool::{ChannelManager, LapinConnectionManager};
 use signaly_error::{
     DatabaseError::{MessageNotSent, PoolCreation, PoolObtention},
     Error, ErrorType,
 };
+pub use subscriber::RabbitMqSubscriber;
+pub use topology::{Binding, Exchange, Queue, Topology};
+
+/// Default number of unacknowledged deliveries sent to a consumer.
+pub const DEFAULT_PREFETCH: u16 = 10;
 
 type Pool = deadpool::managed::Pool<LapinConnectionManager>;
+type ChannelPool = deadpool::managed::Pool<ChannelManager>;
+
+/// Settings of a RabbitMQ consumer.
+#[derive(Debug, Clone)]
+pub struct ConsumerConfig {
+    /// Consumed queue.
+    pub queue: String,
+    /// Consumer tag, which must be unique on the channel.
+    pub tag: String,
+    /// Maximum number of unacknowledged deliveries, `0` for no limit.
+    pub prefetch: u16,
+}
 
 /// Manage RabbitMQ pool connection.
+#[derive(Clone)]
 #[allow(dead_code, missing_debug_implementations)]
 pub struct Manager {
     /// Pool session.
     pub session: Pool,
+    /// Channels used to publish messages, reused between messages.
+    channels: ChannelPool,
 }
 
 impl Manager {
@@ -37,28 +60,145 @@ impl Manager {
     /// // Do what ever you want with your cool new session...
     /// ```
     pub async fn new(host: String) -> Result<Self, Error> {
+        let pool_error = |error: deadpool::managed::BuildError| {
+            Error::new(
+                ErrorType::Database(PoolCreation),
+                Some(Box::new(error)),
+                None,
+            )
+        };
+
+        let session = Pool::builder(LapinConnectionManager::new(
+            host,
+            ConnectionProperties::default(),
+        ))
+        .build()
+        .map_err(pool_error)?;
+
         Ok(Manager {
-            session: Pool::builder(LapinConnectionManager::new(
-                host,
-                ConnectionProperties::default()
+            channels: ChannelPool::builder(ChannelManager::new(
+                session.clone(),
             ))
             .build()
-            .map_err(|error| {
-                Error::new(
-                    ErrorType::Database(PoolCreation),
-                    Some(Box::new(error)),
-                    None,
-                )
-            })?,
+            .map_err(pool_error)?,
+            session,
         })
     }
 
-    /// Send a message via RabbitMQ.
+    /// Check that the connection to the broker is open.
+    pub async fn check_health(&self) -> bool {
+        self.session.get().await.is_ok()
+    }
+
+    /// Publish a message on `exchange` with `routing_key`.
+    ///
+    /// With the default exchange `""`, the message is sent to the queue
+    /// named `routing_key`. Returns once the broker has confirmed the
+    /// message; a message no queue is bound to is returned by the broker
+    /// and reported as not sent.
     pub async fn send(
         &self,
-        topic: String,
+        exchange: &str,
+        routing_key: &str,
         content: String,
     ) -> Result<(), Error> {
+        let not_sent = |error| {
+            Error::new(
+                ErrorType::Database(MessageNotSent),
+                Some(Box::new(error)),
+                None,
+            )
+        };
+
+        let channel = self.channels.get().await.map_err(|error| {
+            Error::new(
+                ErrorType::Database(PoolObtention),
+                Some(Box::new(error)),
+                Some("while trying to get a RabbitMQ channel".to_string()),
+            )
+        })?;
+
+        let confirmation = channel
+            .basic_publish(
+                exchange,
+                routing_key,
+                BasicPublishOptions {
+                    mandatory: true,
+                    ..Default::default()
+                },
+                content.as_bytes(),
+                BasicProperties::default(),
+            )
+            .await
+            .map_err(not_sent)?
+            .await
+            .map_err(not_sent)?;
+
+        match confirmation {
+            Confirmation::Ack(Some(message)) => Err(Error::new(
+                ErrorType::Database(MessageNotSent),
+                None,
+                Some(format!(
+                    "message returned by RabbitMQ: {}",
+                    message.reply_text
+                )),
+            )),
+            Confirmation::Nack(message) => Err(Error::new(
+                ErrorType::Database(MessageNotSent),
+                None,
+                Some(match message {
+                    Some(message) => format!(
+                        "message nacked by RabbitMQ: {}",
+                        message.reply_text
+                    ),
+                    None => "message nacked by RabbitMQ".to_string(),
+                }),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Start consuming `config.queue` on a new channel.
+    pub async fn consume(
+        &self,
+        config: &ConsumerConfig,
+    ) -> Result<Consumer, Error> {
+        let error = |error, context: &str| {
+            Error::new(
+                ErrorType::Unspecified,
+                Some(Box::new(error)),
+                Some(context.to_string()),
+            )
+        };
+
+        let channel = self.channel().await?;
+
+        channel
+            .basic_qos(config.prefetch, BasicQosOptions::default())
+            .await
+            .map_err(|err| error(err, "while setting RabbitMQ prefetch"))?;
+
+        channel
+            .basic_consume(
+                &config.queue,
+                &config.tag,
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .map_err(|err| {
+                error(
+                    err,
+                    &format!(
+                        "while consuming RabbitMQ queue {:?}",
+                        config.queue
+                    ),
+                )
+            })
+    }
+
+    /// Open a channel on a pooled connection.
+    async fn channel(&self) -> Result<Channel, Error> {
         self.session
             .get()
             .await
@@ -66,10 +206,7 @@ impl Manager {
                 Error::new(
                     ErrorType::Database(PoolObtention),
                     Some(Box::new(error)),
-                    Some(
-                        "while trying to send a message via RabbitMQ"
-                            .to_string(),
-                    ),
+                    Some("while trying to open a RabbitMQ channel".to_string()),
                 )
             })?
             .create_channel()
@@ -80,31 +217,50 @@ impl Manager {
                     Some(Box::new(error)),
                     Some("cannot create RabbitMQ channel".to_string()),
                 )
-            })?
-            .basic_publish(
-                "",
-                topic.as_str(),
-                BasicPublishOptions::default(),
-                content.as_bytes(),
-                BasicProperties::default(),
-            )
-            .await
-            .map_err(|error| {
-                Error::new(
-                    ErrorType::Database(MessageNotSent),
-                    Some(Box::new(error)),
-                    None,
-                )
-            })?
-            .await
-            .map_err(|error| {
-                Error::new(
-                    ErrorType::Database(MessageNotSent),
-                    Some(Box::new(error)),
-                    None,
-                )
-            })?;
+            })
+    }
+}
+
+/// Publish messages to an exchange.
+#[derive(Clone)]
+#[allow(missing_debug_implementations)]
+pub struct RabbitMqPublisher {
+    manager: Manager,
+    exchange: Option<String>,
+}
+
+impl RabbitMqPublisher {
+    /// Publish messages on connections of `manager`.
+    ///
+    /// If `exchange` is set, messages are published on it with their
+    /// routing key. Otherwise, they are sent to the queue named by the
+    /// topic.
+    pub fn new(manager: Manager, exchange: Option<String>) -> Self {
+        RabbitMqPublisher { manager, exchange }
+    }
+}
+
+impl Publisher for RabbitMqPublisher {
+    fn name(&self) -> &'static str {
+        "rabbitmq"
+    }
+
+    async fn publish(
+        &self,
+        topic: &str,
+        message: Message<'_>,
+    ) -> Result<(), Error> {
+        match &self.exchange {
+            Some(exchange) => {
+                self.manager
+                    .send(exchange, &message.routing_key, message.content)
+                    .await
+            },
+            None => self.manager.send("", topic, message.content).await,
+        }
+    }
 
-        Ok(())
+    async fn check_health(&self) -> bool {
+        self.manager.check_health().await
     }
 }
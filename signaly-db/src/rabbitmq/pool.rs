@@ -1,5 +1,11 @@
 use deadpool::managed;
-use lapin::{Connection, ConnectionProperties, ConnectionState, Error};
+use lapin::{
+    options::ConfirmSelectOptions, Channel, Connection, ConnectionProperties,
+    ConnectionState, Error,
+};
+use signaly_error::{DatabaseError::PoolObtention, ErrorType};
+
+use super::Pool;
 
 #[allow(missing_debug_implementations)]
 pub struct LapinConnectionManager {
@@ -46,3 +52,72 @@ impl managed::Manager for LapinConnectionManager {
         }
     }
 }
+
+/// Channels of pooled connections, with publisher confirms enabled.
+#[allow(missing_debug_implementations)]
+pub struct ChannelManager {
+    connections: Pool,
+}
+
+impl ChannelManager {
+    /// Creates a new [`ChannelManager`] opening channels on `connections`.
+    pub fn new(connections: Pool) -> ChannelManager {
+        ChannelManager { connections }
+    }
+}
+
+impl managed::Manager for ChannelManager {
+    type Type = Channel;
+    type Error = signaly_error::Error;
+
+    async fn create(&self) -> Result<Self::Type, Self::Error> {
+        let channel = self
+            .connections
+            .get()
+            .await
+            .map_err(|error| {
+                signaly_error::Error::new(
+                    ErrorType::Database(PoolObtention),
+                    Some(Box::new(error)),
+                    Some("while trying to open a RabbitMQ channel".to_string()),
+                )
+            })?
+            .create_channel()
+            .await
+            .map_err(|error| {
+                channel_error(error, "cannot create RabbitMQ channel")
+            })?;
+
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .map_err(|error| {
+                channel_error(
+                    error,
+                    "cannot enable RabbitMQ publisher confirms",
+                )
+            })?;
+
+        Ok(channel)
+    }
+
+    async fn recycle(
+        &self,
+        channel: &mut Self::Type,
+        _: &managed::Metrics,
+    ) -> managed::RecycleResult<Self::Error> {
+        if channel.status().connected() {
+            Ok(())
+        } else {
+            Err(managed::RecycleError::message("Closed channel"))
+        }
+    }
+}
+
+fn channel_error(error: Error, context: &str) -> signaly_error::Error {
+    signaly_error::Error::new(
+        ErrorType::Unspecified,
+        Some(Box::new(error)),
+        Some(context.to_string()),
+    )
+}
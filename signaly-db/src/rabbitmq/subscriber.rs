@@ -0,0 +1,121 @@
+//! Receive messages from RabbitMQ.
+
+use std::collections::HashMap;
+
+use futures_lite::stream::StreamExt;
+use lapin::{
+    acker::Acker,
+    options::{BasicAckOptions, BasicNackOptions},
+    Consumer,
+};
+use signaly_error::{Error, ErrorType};
+
+use super::{ConsumerConfig, Manager};
+use crate::broker::{Acknowledgement, Delivery, Origin, Subscriber};
+
+/// RabbitMQ consumer, created again on a new connection if it stops.
+#[allow(missing_debug_implementations)]
+pub struct RabbitMqSubscriber {
+    manager: Manager,
+    config: ConsumerConfig,
+    consumer: Option<Consumer>,
+    /// Acknowledgers of unacknowledged deliveries, by delivery tag.
+    pending: HashMap<u64, Acker>,
+}
+
+impl RabbitMqSubscriber {
+    /// Consume `config.queue` on connections of `manager`.
+    pub fn new(manager: Manager, config: ConsumerConfig) -> Self {
+        RabbitMqSubscriber {
+            manager,
+            config,
+            consumer: None,
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl Subscriber for RabbitMqSubscriber {
+    /// Rejected messages are sent to the dead letter exchange of the
+    /// queue, if any.
+    const REQUEUES: bool = true;
+
+    fn name(&self) -> &'static str {
+        "rabbitmq"
+    }
+
+    async fn receive(&mut self) -> Result<Vec<Delivery>, Error> {
+        let Some(consumer) = self.consumer.as_mut() else {
+            // Deliveries of a lost channel are redelivered by the broker.
+            self.pending.clear();
+            self.consumer = Some(self.manager.consume(&self.config).await?);
+            return Ok(Vec::new());
+        };
+
+        match consumer.next().await {
+            Some(Ok(delivery)) => {
+                self.pending.insert(delivery.delivery_tag, delivery.acker);
+
+                Ok(vec![Delivery {
+                    origin: Origin {
+                        topic: self.config.queue.clone(),
+                        delivery_tag: Some(delivery.delivery_tag),
+                        ..Default::default()
+                    },
+                    payload: delivery.data,
+                }])
+            },
+            Some(Err(err)) => {
+                self.consumer = None;
+                Err(Error::new(
+                    ErrorType::Unspecified,
+                    Some(Box::new(err)),
+                    Some("while receiving RabbitMQ messages".to_string()),
+                ))
+            },
+            None => {
+                self.consumer = None;
+                Err(Error::new(
+                    ErrorType::Unspecified,
+                    None,
+                    Some("RabbitMQ consumer stopped".to_string()),
+                ))
+            },
+        }
+    }
+
+    async fn acknowledge(
+        &mut self,
+        origin: &Origin,
+        acknowledgement: Acknowledgement,
+    ) -> Result<(), Error> {
+        let Some(acker) = origin
+            .delivery_tag
+            .and_then(|delivery_tag| self.pending.remove(&delivery_tag))
+        else {
+            return Ok(());
+        };
+
+        let result = match acknowledgement {
+            Acknowledgement::Ack => acker.ack(BasicAckOptions::default()).await,
+            Acknowledgement::Requeue | Acknowledgement::Reject => {
+                acker
+                    .nack(BasicNackOptions {
+                        requeue: acknowledgement == Acknowledgement::Requeue,
+                        ..Default::default()
+                    })
+                    .await
+            },
+        };
+
+        result.map_err(|err| {
+            // The channel is closed, consume on a new one.
+            self.consumer = None;
+            Error::new(
+                ErrorType::Unspecified,
+                Some(Box::new(err)),
+                Some("while acknowledging a RabbitMQ message".to_string()),
+            )
+        })
+    }
+}
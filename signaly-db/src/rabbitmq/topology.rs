@@ -0,0 +1,143 @@
+//! Exchanges, queues and bindings declared by Signaly.
+
+use lapin::{
+    options::{ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions},
+    types::{AMQPValue, FieldTable},
+    ExchangeKind,
+};
+use signaly_error::{DatabaseError::QueryFailed, Error, ErrorType};
+
+use super::Manager;
+
+/// Exchange routing messages to queues.
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    /// Name of the exchange.
+    pub name: String,
+    /// How messages are routed.
+    pub kind: ExchangeKind,
+    /// Keep the exchange when the broker restarts.
+    pub durable: bool,
+}
+
+/// Queue storing messages until they are consumed.
+#[derive(Debug, Clone)]
+pub struct Queue {
+    /// Name of the queue.
+    pub name: String,
+    /// Keep the queue when the broker restarts.
+    pub durable: bool,
+    /// Only allow the declaring connection to use the queue.
+    pub exclusive: bool,
+    /// Delete the queue when its last consumer is cancelled.
+    pub auto_delete: bool,
+    /// Exchange receiving rejected messages which are not requeued. They
+    /// are dropped if not set.
+    pub dead_letter_exchange: Option<String>,
+}
+
+/// Route messages of `exchange` whose routing key matches `routing_key` to
+/// `queue`.
+#[derive(Debug, Clone)]
+pub struct Binding {
+    /// Name of the bound queue.
+    pub queue: String,
+    /// Name of the exchange.
+    pub exchange: String,
+    /// Routing key, or pattern for topic exchanges such as `sanction.#`.
+    pub routing_key: String,
+}
+
+/// Exchanges, queues and bindings to declare.
+#[derive(Debug, Clone, Default)]
+pub struct Topology {
+    /// Exchanges, declared first.
+    pub exchanges: Vec<Exchange>,
+    /// Queues.
+    pub queues: Vec<Queue>,
+    /// Bindings, declared last.
+    pub bindings: Vec<Binding>,
+}
+
+impl Manager {
+    /// Declare `topology`.
+    ///
+    /// Declarations are idempotent, but fail if an exchange or a queue
+    /// already exists with other settings.
+    pub async fn declare(&self, topology: &Topology) -> Result<(), Error> {
+        let channel = self.channel().await?;
+
+        for exchange in &topology.exchanges {
+            channel
+                .exchange_declare(
+                    &exchange.name,
+                    exchange.kind.clone(),
+                    ExchangeDeclareOptions {
+                        durable: exchange.durable,
+                        ..Default::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await
+                .map_err(|error| {
+                    declare_error(error, "exchange", &exchange.name)
+                })?;
+        }
+
+        for queue in &topology.queues {
+            let mut arguments = FieldTable::default();
+            if let Some(exchange) = &queue.dead_letter_exchange {
+                arguments.insert(
+                    "x-dead-letter-exchange".into(),
+                    AMQPValue::LongString(exchange.as_str().into()),
+                );
+            }
+
+            channel
+                .queue_declare(
+                    &queue.name,
+                    QueueDeclareOptions {
+                        durable: queue.durable,
+                        exclusive: queue.exclusive,
+                        auto_delete: queue.auto_delete,
+                        ..Default::default()
+                    },
+                    arguments,
+                )
+                .await
+                .map_err(|error| declare_error(error, "queue", &queue.name))?;
+        }
+
+        for binding in &topology.bindings {
+            channel
+                .queue_bind(
+                    &binding.queue,
+                    &binding.exchange,
+                    &binding.routing_key,
+                    QueueBindOptions::default(),
+                    FieldTable::default(),
+                )
+                .await
+                .map_err(|error| {
+                    declare_error(
+                        error,
+                        "binding",
+                        &format!("{} -> {}", binding.exchange, binding.queue),
+                    )
+                })?;
+        }
+
+        // Declarations are done, the channel is not reused.
+        let _ = channel.close(200, "OK").await;
+
+        Ok(())
+    }
+}
+
+fn declare_error(error: lapin::Error, kind: &str, name: &str) -> Error {
+    Error::new(
+        ErrorType::Database(QueryFailed),
+        Some(Box::new(error)),
+        Some(format!("while declaring RabbitMQ {} {:?}", kind, name)),
+    )
+}
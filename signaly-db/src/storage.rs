@@ -0,0 +1,138 @@
+//! Records saved by Signaly and storage backends.
+
+use std::future::Future;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use signaly_error::Error;
+use uuid::Uuid;
+
+/// Report saved in `reports` and `reports_by_target` tables.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cassandra", derive(scylla::FromRow))]
+pub struct Report {
+    /// Unique identifier of the report.
+    pub id: Uuid,
+    /// Day the report was made.
+    pub date: NaiveDate,
+    /// Time the report was made, `None` for reports saved before it was
+    /// recorded.
+    pub time: Option<DateTime<Utc>>,
+    /// Service which emitted the report.
+    pub source: String,
+    /// User or content reported.
+    pub target: String,
+    /// Numeric code of the reason.
+    pub reason: i32,
+    /// Free text explaining the reason, if any.
+    pub text_reason: Option<String>,
+}
+
+/// Sanction saved in `sanctions` table.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cassandra", derive(scylla::FromRow))]
+pub struct Sanction {
+    /// Unique identifier of the sanction.
+    pub id: Uuid,
+    /// Day the sanction was taken.
+    pub date: NaiveDate,
+    /// Service which emitted the sanction.
+    pub source: String,
+    /// User or content sanctioned.
+    pub target: String,
+    /// Numeric code of the reason, saved in `reason_code`.
+    pub reason: i32,
+    /// Free text explaining the reason, if any.
+    pub text_reason: Option<String>,
+    /// Numeric code of the sanction taken, if any.
+    pub sanction: Option<i32>,
+    /// End of the sanction, `None` if permanent.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Numeric code of the sanction status.
+    pub status: Option<i32>,
+}
+
+/// Message which cannot be processed, saved in `dead_letters` table.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cassandra", derive(scylla::FromRow))]
+pub struct DeadLetter {
+    /// Unique identifier of the dead letter.
+    pub id: Uuid,
+    /// Time the message was received.
+    pub received_at: DateTime<Utc>,
+    /// Raw content of the message.
+    pub payload: Vec<u8>,
+    /// Why the message cannot be processed.
+    pub error: String,
+    /// Kafka topic or RabbitMQ queue the message was received from.
+    pub topic: Option<String>,
+    /// Kafka partition of the message.
+    pub partition: Option<i32>,
+    /// Kafka offset of the message.
+    pub offset: Option<i64>,
+    /// RabbitMQ delivery tag of the message.
+    pub delivery_tag: Option<i64>,
+}
+
+/// Database in which events are saved.
+pub trait Storage: Send + Sync {
+    /// Save a report.
+    fn insert_report(
+        &self,
+        report: &Report,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Save a sanction.
+    fn insert_sanction(
+        &self,
+        sanction: &Sanction,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Save a message which cannot be processed.
+    fn insert_dead_letter(
+        &self,
+        letter: &DeadLetter,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Get reports made against `target` between `from` and `to`
+    /// included, most recent first.
+    fn reports_between(
+        &self,
+        target: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> impl Future<Output = Result<Vec<Report>, Error>> + Send;
+
+    /// Get every sanction taken against `target`.
+    fn sanctions_against(
+        &self,
+        target: &str,
+    ) -> impl Future<Output = Result<Vec<Sanction>, Error>> + Send;
+
+    /// Record that the event `id` sent by `source` has been processed.
+    fn mark_event_processed(
+        &self,
+        source: &str,
+        id: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Check whether the event `id` sent by `source` has been recorded by
+    /// [`Storage::mark_event_processed`].
+    fn is_event_processed(
+        &self,
+        source: &str,
+        id: &str,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Record that the message `id` has been delivered to `broker`.
+    fn mark_delivered(
+        &self,
+        id: Uuid,
+        broker: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Get the brokers the message `id` has been delivered to.
+    fn deliveries(
+        &self,
+        id: Uuid,
+    ) -> impl Future<Output = Result<Vec<String>, Error>> + Send;
+}
@@ -0,0 +1,399 @@
+//! TLS and SASL connections to a local Kafka broker stand-in.
+#![cfg(feature = "apache_kafka")]
+
+use std::io::Read;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use openssl::{
+    asn1::Asn1Time,
+    bn::{BigNum, MsbOption},
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{PKey, Private},
+    ssl::{SslAcceptor, SslMethod, SslVerifyMode},
+    x509::{
+        extension::{BasicConstraints, SubjectAlternativeName},
+        X509NameBuilder, X509,
+    },
+};
+use signaly_db::kafka::{
+    new_consumer, ConsumerConfig, Manager, ProducerConfig, SaslConfig,
+    SaslMechanism, TlsConfig,
+};
+use tempfile::TempDir;
+
+/// Kafka API key of API versions requests, the first request of clients.
+const API_VERSIONS: i16 = 18;
+
+struct Identity {
+    cert: X509,
+    key: PKey<Private>,
+}
+
+/// Create a certificate for `name`, self-signed if `issuer` is not set.
+fn identity(name: &str, issuer: Option<&Identity>) -> Identity {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+
+    let mut subject = X509NameBuilder::new().unwrap();
+    subject.append_entry_by_nid(Nid::COMMONNAME, name).unwrap();
+    let subject = subject.build();
+
+    let mut serial = BigNum::new().unwrap();
+    serial.rand(64, MsbOption::MAYBE_ZERO, false).unwrap();
+
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    builder
+        .set_serial_number(&serial.to_asn1_integer().unwrap())
+        .unwrap();
+    builder.set_subject_name(&subject).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+
+    match issuer {
+        Some(issuer) => {
+            builder.set_issuer_name(issuer.cert.subject_name()).unwrap();
+            let names = SubjectAlternativeName::new()
+                .dns(name)
+                .build(&builder.x509v3_context(Some(&issuer.cert), None))
+                .unwrap();
+            builder.append_extension(names).unwrap();
+            builder.sign(&issuer.key, MessageDigest::sha256()).unwrap();
+        },
+        None => {
+            builder.set_issuer_name(&subject).unwrap();
+            builder
+                .append_extension(
+                    BasicConstraints::new().critical().ca().build().unwrap(),
+                )
+                .unwrap();
+            builder.sign(&key, MessageDigest::sha256()).unwrap();
+        },
+    }
+
+    Identity {
+        cert: builder.build(),
+        key,
+    }
+}
+
+/// Certificates shared by the broker and the client.
+struct Pki {
+    ca: Identity,
+    ca_file: PathBuf,
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    /// Directory of the files, removed when dropped.
+    _dir: TempDir,
+}
+
+impl Pki {
+    fn new() -> Self {
+        let ca = identity("Signaly test CA", None);
+        let client = identity("signaly", Some(&ca));
+
+        let dir = TempDir::new().unwrap();
+        let ca_file = dir.path().join("ca.pem");
+        let cert_file = dir.path().join("client.pem");
+        let key_file = dir.path().join("client.key");
+        std::fs::write(&ca_file, ca.cert.to_pem().unwrap()).unwrap();
+        std::fs::write(&cert_file, client.cert.to_pem().unwrap()).unwrap();
+        std::fs::write(
+            &key_file,
+            client.key.private_key_to_pem_pkcs8().unwrap(),
+        )
+        .unwrap();
+
+        Pki {
+            ca,
+            ca_file,
+            cert_file,
+            key_file,
+            _dir: dir,
+        }
+    }
+
+    fn tls(&self) -> TlsConfig {
+        TlsConfig {
+            ca_file: Some(self.ca_file.clone()),
+            cert_file: Some(self.cert_file.clone()),
+            key_file: Some(self.key_file.clone()),
+            verify_hostname: true,
+        }
+    }
+}
+
+/// First request received by the broker.
+#[derive(Debug)]
+struct Request {
+    /// Common name of the client certificate.
+    client: String,
+    api_key: i16,
+}
+
+/// Start a broker stand-in for `localhost` requiring client certificates
+/// issued by the test CA.
+///
+/// It answers no request: connections are closed once the request header
+/// is read, or the TLS handshake failed.
+fn broker(pki: &Pki) -> (u16, Receiver<Result<Request, String>>) {
+    let server = identity("localhost", Some(&pki.ca));
+
+    let mut acceptor =
+        SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+    acceptor.set_certificate(&server.cert).unwrap();
+    acceptor.set_private_key(&server.key).unwrap();
+    acceptor
+        .cert_store_mut()
+        .add_cert(pki.ca.cert.clone())
+        .unwrap();
+    acceptor
+        .set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    let acceptor = acceptor.build();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = match acceptor.accept(stream.unwrap()) {
+                Ok(mut stream) => {
+                    // Size (i32), then API key (i16).
+                    let mut header = [0; 6];
+                    if let Err(error) = stream.read_exact(&mut header) {
+                        let _ = sender.send(Err(error.to_string()));
+                        continue;
+                    }
+
+                    let client = stream
+                        .ssl()
+                        .peer_certificate()
+                        .and_then(|cert| {
+                            cert.subject_name()
+                                .entries_by_nid(Nid::COMMONNAME)
+                                .next()
+                                .map(|entry| entry.data().as_utf8().unwrap())
+                                .map(|name| name.to_string())
+                        })
+                        .unwrap_or_default();
+
+                    Ok(Request {
+                        client,
+                        api_key: i16::from_be_bytes([header[4], header[5]]),
+                    })
+                },
+                Err(error) => Err(error.to_string()),
+            };
+
+            if sender.send(result).is_err() {
+                break;
+            }
+        }
+    });
+
+    (port, receiver)
+}
+
+fn first_request(
+    receiver: &Receiver<Result<Request, String>>,
+) -> Result<Request, String> {
+    receiver.recv_timeout(Duration::from_secs(10)).unwrap()
+}
+
+#[tokio::test]
+async fn consumer_authenticates_with_client_certificate() {
+    let pki = Pki::new();
+    let (port, requests) = broker(&pki);
+
+    let consumer = new_consumer(
+        vec![format!("localhost:{}", port)],
+        ConsumerConfig {
+            topics: vec!["compliance".to_string()],
+            tls: Some(pki.tls()),
+            ..Default::default()
+        },
+    )
+    .await;
+    // The stand-in does not answer.
+    assert!(consumer.is_err());
+
+    let request = first_request(&requests).unwrap();
+    assert_eq!(request.client, "signaly");
+    assert_eq!(request.api_key, API_VERSIONS);
+}
+
+#[tokio::test]
+async fn producer_authenticates_with_client_certificate() {
+    let pki = Pki::new();
+    let (port, requests) = broker(&pki);
+
+    let manager = Manager::new(
+        vec![format!("localhost:{}", port)],
+        ProducerConfig {
+            retries: 0,
+            tls: Some(pki.tls()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(manager
+        .send("sanction".to_string(), None, "{}".to_string())
+        .await
+        .is_err());
+
+    let request = first_request(&requests).unwrap();
+    assert_eq!(request.client, "signaly");
+    assert_eq!(request.api_key, API_VERSIONS);
+}
+
+#[tokio::test]
+async fn host_name_is_verified() {
+    let pki = Pki::new();
+    let (port, requests) = broker(&pki);
+
+    // The broker certificate is issued for `localhost` only.
+    let consumer = new_consumer(
+        vec![format!("127.0.0.1:{}", port)],
+        ConsumerConfig {
+            topics: vec!["compliance".to_string()],
+            tls: Some(pki.tls()),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert!(consumer.is_err());
+    assert!(first_request(&requests).is_err());
+}
+
+#[tokio::test]
+async fn host_name_verification_can_be_disabled() {
+    let pki = Pki::new();
+    let (port, requests) = broker(&pki);
+
+    let _ = new_consumer(
+        vec![format!("127.0.0.1:{}", port)],
+        ConsumerConfig {
+            topics: vec!["compliance".to_string()],
+            tls: Some(TlsConfig {
+                verify_hostname: false,
+                ..pki.tls()
+            }),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    assert_eq!(first_request(&requests).unwrap().api_key, API_VERSIONS);
+}
+
+#[tokio::test]
+async fn untrusted_broker_is_rejected() {
+    let pki = Pki::new();
+    let (port, requests) = broker(&pki);
+
+    let consumer = new_consumer(
+        vec![format!("localhost:{}", port)],
+        ConsumerConfig {
+            topics: vec!["compliance".to_string()],
+            tls: Some(TlsConfig {
+                ca_file: None,
+                ..pki.tls()
+            }),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert!(consumer.is_err());
+    assert!(first_request(&requests).is_err());
+}
+
+#[tokio::test]
+async fn client_without_certificate_is_rejected() {
+    let pki = Pki::new();
+    let (port, requests) = broker(&pki);
+
+    let consumer = new_consumer(
+        vec![format!("localhost:{}", port)],
+        ConsumerConfig {
+            topics: vec!["compliance".to_string()],
+            tls: Some(TlsConfig {
+                cert_file: None,
+                key_file: None,
+                ..pki.tls()
+            }),
+            ..Default::default()
+        },
+    )
+    .await;
+    assert!(consumer.is_err());
+    assert!(first_request(&requests).is_err());
+}
+
+#[tokio::test]
+async fn producer_authenticates_with_sasl_over_tls() {
+    let pki = Pki::new();
+    let (port, requests) = broker(&pki);
+
+    let manager = Manager::new(
+        vec![format!("localhost:{}", port)],
+        ProducerConfig {
+            retries: 0,
+            tls: Some(pki.tls()),
+            sasl: Some(SaslConfig {
+                mechanism: SaslMechanism::ScramSha512,
+                username: "signaly".to_string(),
+                password: "secret".to_string(),
+            }),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(manager
+        .send("sanction".to_string(), None, "{}".to_string())
+        .await
+        .is_err());
+
+    // Supported versions of the SASL handshake are asked first.
+    let request = first_request(&requests).unwrap();
+    assert_eq!(request.client, "signaly");
+    assert_eq!(request.api_key, API_VERSIONS);
+}
+
+#[tokio::test]
+async fn sasl_mechanisms_are_supported() {
+    for mechanism in [
+        SaslMechanism::Plain,
+        SaslMechanism::ScramSha256,
+        SaslMechanism::ScramSha512,
+    ] {
+        // Clients are created without connecting to brokers, but fail on
+        // unsupported mechanisms.
+        let manager = Manager::new(
+            vec!["localhost:9092".to_string()],
+            ProducerConfig {
+                sasl: Some(SaslConfig {
+                    mechanism,
+                    username: "signaly".to_string(),
+                    password: "secret".to_string(),
+                }),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(manager.is_ok(), "{}", mechanism.as_str());
+    }
+}
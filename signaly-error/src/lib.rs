@@ -90,6 +90,10 @@ pub enum DatabaseError {
     PoolObtention,
     /// The message for the broker has not been sent.
     MessageNotSent,
+    /// The query has not been executed by the database.
+    QueryFailed,
+    /// The rows returned by the database do not match the expected type.
+    InvalidRows,
 }
 
 impl fmt::Display for DatabaseError {
@@ -104,6 +108,13 @@ impl fmt::Display for DatabaseError {
             DatabaseError::MessageNotSent => {
                 write!(f, "The message for the broker has not been sent.")
             },
+            DatabaseError::QueryFailed => {
+                write!(f, "The query has not been executed by the database.")
+            },
+            DatabaseError::InvalidRows => write!(
+                f,
+                "The rows returned by the database do not match the expected type."
+            ),
         }
     }
 }
@@ -6,7 +6,7 @@
 //!
 //! ```
 
-use prometheus::{Encoder, IntCounterVec, Opts, Registry};
+use prometheus::{Encoder, IntCounterVec, IntGaugeVec, Opts, Registry};
 use signaly_error::{Error, ErrorType, IoError::WriteError};
 use std::{
     convert::Infallible,
@@ -29,6 +29,30 @@ lazy_static! {
         &["reason", "moderator", "sanction"]
     )
     .expect("sanctions metric could not be created");
+    // metrics about events received more than once.
+    pub static ref DUPLICATES_COLLECTOR: IntCounterVec = IntCounterVec::new(
+        Opts::new("duplicate_events", "Events ignored because already processed"),
+        &["platform"]
+    )
+    .expect("duplicate events metric could not be created");
+    // metrics about invalid events.
+    pub static ref REJECTIONS_COLLECTOR: IntCounterVec = IntCounterVec::new(
+        Opts::new("rejected_events", "Events rejected because invalid"),
+        &["field", "reason"]
+    )
+    .expect("rejected events metric could not be created");
+    // metrics about connections to message brokers.
+    pub static ref BROKER_HEALTH: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("broker_up", "Whether the message broker answers health checks"),
+        &["broker"]
+    )
+    .expect("broker health metric could not be created");
+    // metrics about consumers reconnected to message brokers.
+    pub static ref BROKER_RECONNECTIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new("broker_reconnections", "Consumers reconnected after losing their broker"),
+        &["broker"]
+    )
+    .expect("broker reconnections metric could not be created");
 }
 
 #[inline]
@@ -40,6 +64,22 @@ fn register_custom_metrics() {
     REGISTRY
         .register(Box::new(SANCTIONS_COLLECTOR.clone()))
         .expect("collector can be registered");
+
+    REGISTRY
+        .register(Box::new(DUPLICATES_COLLECTOR.clone()))
+        .expect("collector can be registered");
+
+    REGISTRY
+        .register(Box::new(REJECTIONS_COLLECTOR.clone()))
+        .expect("collector can be registered");
+
+    REGISTRY
+        .register(Box::new(BROKER_HEALTH.clone()))
+        .expect("collector can be registered");
+
+    REGISTRY
+        .register(Box::new(BROKER_RECONNECTIONS.clone()))
+        .expect("collector can be registered");
 }
 
 #[inline]
@@ -0,0 +1,150 @@
+//! Message brokers from which Signaly receives messages and on which it
+//! publishes the ones it produces.
+
+#[cfg(feature = "rabbitmq")]
+pub mod topology;
+
+pub use signaly_db::broker::{Message, Origin};
+use signaly_db::storage::Storage;
+use signaly_error::{Error, ErrorType};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::models::DeadLetterEnvelope;
+
+/// Broker on which produced messages are sent.
+#[allow(missing_debug_implementations)]
+pub enum Publisher {
+    /// Apache Kafka producer pool.
+    #[cfg(feature = "kafka")]
+    Kafka(signaly_db::kafka::Manager),
+    /// RabbitMQ publisher.
+    #[cfg(feature = "rabbitmq")]
+    RabbitMq(signaly_db::rabbitmq::RabbitMqPublisher),
+}
+
+impl signaly_db::broker::Publisher for Publisher {
+    fn name(&self) -> &'static str {
+        match *self {
+            #[cfg(feature = "kafka")]
+            Publisher::Kafka(ref manager) => manager.name(),
+            #[cfg(feature = "rabbitmq")]
+            Publisher::RabbitMq(ref publisher) => publisher.name(),
+        }
+    }
+
+    async fn publish(
+        &self,
+        topic: &str,
+        message: Message<'_>,
+    ) -> Result<(), Error> {
+        match *self {
+            #[cfg(feature = "kafka")]
+            Publisher::Kafka(ref manager) => {
+                manager.publish(topic, message).await
+            },
+            #[cfg(feature = "rabbitmq")]
+            Publisher::RabbitMq(ref publisher) => {
+                publisher.publish(topic, message).await
+            },
+        }
+    }
+
+    async fn check_health(&self) -> bool {
+        match *self {
+            #[cfg(feature = "kafka")]
+            Publisher::Kafka(ref manager) => manager.check_health().await,
+            #[cfg(feature = "rabbitmq")]
+            Publisher::RabbitMq(ref publisher) => {
+                publisher.check_health().await
+            },
+        }
+    }
+}
+
+/// Queue receiving the messages of a broker which cannot be processed.
+#[allow(missing_debug_implementations)]
+pub struct DeadLetterQueue<P> {
+    /// Broker the messages were received from.
+    pub publisher: P,
+    /// Kafka topic or RabbitMQ queue.
+    pub destination: String,
+}
+
+impl<P: signaly_db::broker::Publisher> DeadLetterQueue<P> {
+    /// Send `envelope` to the queue, returning once the broker confirmed
+    /// it.
+    pub async fn send(
+        &self,
+        envelope: &DeadLetterEnvelope,
+    ) -> Result<(), Error> {
+        let content = serde_json::to_string(envelope).map_err(|error| {
+            Error::new(
+                ErrorType::Unspecified,
+                Some(Box::new(error)),
+                Some("while serializing a dead letter".to_string()),
+            )
+        })?;
+
+        self.publisher
+            .publish(
+                &self.destination,
+                Message {
+                    key: None,
+                    routing_key: self.destination.clone(),
+                    content,
+                },
+            )
+            .await
+    }
+}
+
+/// Send the message `id` to `topic` on every broker of `publishers`.
+///
+/// Brokers which received the message are recorded in `storage`, so a
+/// message sent again is only sent to the brokers which failed. The
+/// message is sent to each broker, even if one fails. The first error is
+/// then returned.
+///
+/// Delivery is at least once: a message may be received twice by a
+/// broker, such as if Signaly stops before recording the delivery.
+pub async fn deliver<S, P>(
+    storage: &S,
+    publishers: &[P],
+    id: Uuid,
+    topic: &str,
+    message: Message<'_>,
+) -> Result<(), Error>
+where
+    S: Storage,
+    P: signaly_db::broker::Publisher,
+{
+    let delivered = storage.deliveries(id).await?;
+    let mut result = Ok(());
+
+    for publisher in publishers {
+        let broker = publisher.name();
+        if delivered.iter().any(|name| name == broker) {
+            continue;
+        }
+
+        let sent = match publisher.publish(topic, message.clone()).await {
+            Ok(()) => storage.mark_delivered(id, broker).await,
+            Err(error) => Err(error),
+        };
+
+        if let Err(error) = sent {
+            warn!(
+                error = error.to_string(),
+                broker = broker,
+                id = id.to_string(),
+                "Message could not be delivered."
+            );
+            if result.is_ok() {
+                result = Err(error);
+            }
+        }
+    }
+
+    result
+}
@@ -0,0 +1,227 @@
+//! RabbitMQ topology loaded from a TOML file.
+//!
+//! # Example
+//! ```toml
+//! # Sanctions are published with routing keys such as `sanction.removal`.
+//! [[exchanges]]
+//! name = "sanctions"
+//! kind = "topic"
+//!
+//! [[exchanges]]
+//! name = "rejected"
+//! kind = "fanout"
+//!
+//! [[queues]]
+//! name = "compliance"
+//! dead_letter_exchange = "rejected"
+//!
+//! [[queues]]
+//! name = "removals"
+//!
+//! [[bindings]]
+//! queue = "removals"
+//! exchange = "sanctions"
+//! routing_key = "sanction.removal.#"
+//! ```
+//!
+//! `kind` is `topic` by default and can also be `direct`, `fanout` or
+//! `headers`. Exchanges and queues are `durable` by default, queues can be
+//! `exclusive` or `auto_delete`. Messages which cannot be processed are sent
+//! to the `dead_letter_exchange` of their queue, if any.
+
+use std::{fmt, path::Path};
+
+use serde::Deserialize;
+use signaly_db::rabbitmq::{Binding, Exchange, ExchangeKind, Queue, Topology};
+
+/// Read and validate a topology file.
+pub fn from_file(path: &Path) -> Result<Topology, TopologyError> {
+    let content = std::fs::read_to_string(path).map_err(|error| {
+        TopologyError(vec![format!(
+            "cannot read {}: {}",
+            path.display(),
+            error
+        )])
+    })?;
+
+    parse(&content)
+}
+
+/// Parse and validate a topology.
+pub fn parse(content: &str) -> Result<Topology, TopologyError> {
+    let file: TopologyFile = toml::from_str(content)
+        .map_err(|error| TopologyError(vec![error.to_string()]))?;
+
+    let mut errors = Vec::new();
+
+    let exchanges = file
+        .exchanges
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, exchange)| {
+            let kind = match exchange.kind.as_str() {
+                "direct" => Some(ExchangeKind::Direct),
+                "fanout" => Some(ExchangeKind::Fanout),
+                "topic" => Some(ExchangeKind::Topic),
+                "headers" => Some(ExchangeKind::Headers),
+                kind => {
+                    errors.push(format!(
+                        "exchange #{}: unknown kind {:?}",
+                        index + 1,
+                        kind
+                    ));
+                    None
+                },
+            };
+            if exchange.name.is_empty() {
+                errors.push(format!(
+                    "exchange #{}: name must not be empty",
+                    index + 1
+                ));
+            }
+
+            kind.map(|kind| Exchange {
+                name: exchange.name,
+                kind,
+                durable: exchange.durable,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let queues = file
+        .queues
+        .into_iter()
+        .enumerate()
+        .map(|(index, queue)| {
+            if queue.name.is_empty() {
+                errors.push(format!(
+                    "queue #{}: name must not be empty",
+                    index + 1
+                ));
+            }
+            if let Some(exchange) = &queue.dead_letter_exchange {
+                if !is_declared(&exchanges, exchange) {
+                    errors.push(format!(
+                        "queue #{}: undeclared dead letter exchange {:?}",
+                        index + 1,
+                        exchange
+                    ));
+                }
+            }
+
+            Queue {
+                name: queue.name,
+                durable: queue.durable,
+                exclusive: queue.exclusive,
+                auto_delete: queue.auto_delete,
+                dead_letter_exchange: queue.dead_letter_exchange,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let bindings = file
+        .bindings
+        .into_iter()
+        .enumerate()
+        .map(|(index, binding)| {
+            if !queues.iter().any(|queue| queue.name == binding.queue) {
+                errors.push(format!(
+                    "binding #{}: undeclared queue {:?}",
+                    index + 1,
+                    binding.queue
+                ));
+            }
+            if !is_declared(&exchanges, &binding.exchange) {
+                errors.push(format!(
+                    "binding #{}: undeclared exchange {:?}",
+                    index + 1,
+                    binding.exchange
+                ));
+            }
+
+            Binding {
+                queue: binding.queue,
+                exchange: binding.exchange,
+                routing_key: binding.routing_key,
+            }
+        })
+        .collect();
+
+    if errors.is_empty() {
+        Ok(Topology {
+            exchanges,
+            queues,
+            bindings,
+        })
+    } else {
+        Err(TopologyError(errors))
+    }
+}
+
+/// Whether exchange `name` is in `exchanges` or predeclared by RabbitMQ.
+fn is_declared(exchanges: &[Exchange], name: &str) -> bool {
+    name.starts_with("amq.")
+        || exchanges.iter().any(|exchange| exchange.name == name)
+}
+
+/// Errors found in a topology file.
+#[derive(Debug)]
+pub struct TopologyError(pub Vec<String>);
+
+impl fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid RabbitMQ topology: {}", self.0.join("; "))
+    }
+}
+
+impl std::error::Error for TopologyError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TopologyFile {
+    #[serde(default)]
+    exchanges: Vec<ExchangeDefinition>,
+    #[serde(default)]
+    queues: Vec<QueueDefinition>,
+    #[serde(default)]
+    bindings: Vec<BindingDefinition>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExchangeDefinition {
+    name: String,
+    #[serde(default = "default_kind")]
+    kind: String,
+    #[serde(default = "default_durable")]
+    durable: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QueueDefinition {
+    name: String,
+    #[serde(default = "default_durable")]
+    durable: bool,
+    #[serde(default)]
+    exclusive: bool,
+    #[serde(default)]
+    auto_delete: bool,
+    dead_letter_exchange: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BindingDefinition {
+    queue: String,
+    exchange: String,
+    routing_key: String,
+}
+
+fn default_kind() -> String {
+    "topic".to_string()
+}
+
+fn default_durable() -> bool {
+    true
+}
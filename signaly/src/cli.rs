@@ -0,0 +1,187 @@
+//! Command line interface.
+
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use signaly_db::cassandra::{migration::MigrationStatus, Manager};
+use signaly_error::Error;
+
+use crate::config::{Config, ConfigError};
+
+/// Report and sanction aggregator to perform targeted research.
+///
+/// Without subcommand, Signaly consumes messages from the broker.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Settings overriding the configuration file and environment variables.
+#[derive(Debug, Args)]
+pub struct ConfigArgs {
+    /// TOML configuration file, `SIGNALY_CONFIG` by default.
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Port of the HTTP API.
+    #[arg(long, global = true)]
+    pub api_port: Option<u16>,
+    /// Apache Cassandra hosts, separated by commas.
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub cassandra_hosts: Option<Vec<String>>,
+    /// Apache Kafka brokers, separated by commas.
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub kafka_brokers: Option<Vec<String>>,
+    /// Consumed Kafka topics, separated by commas.
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub topic: Option<Vec<String>>,
+    /// Address of the RabbitMQ broker.
+    #[arg(long, global = true)]
+    pub amqp_broker: Option<String>,
+    /// Consumed RabbitMQ queue.
+    #[arg(long, global = true)]
+    pub amqp_queue: Option<String>,
+    /// TOML file of sanction rules.
+    #[arg(long, global = true)]
+    pub sanction_policy: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Apply pending schema migrations.
+    Migrate {
+        /// Print pending migrations without applying them.
+        #[arg(long)]
+        dry_run: bool,
+        #[command(subcommand)]
+        action: Option<MigrateAction>,
+    },
+    /// Process dead letters, or events of a NDJSON file, again.
+    Replay(ReplayArgs),
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Validate the configuration and print it, without passwords.
+    Check,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateAction {
+    /// Show applied and pending migrations.
+    Status,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    /// NDJSON file of events to replay instead of dead letters.
+    #[arg(long)]
+    pub file: Option<PathBuf>,
+    /// Only replay events of this CloudEvents type.
+    #[arg(long = "type")]
+    pub r#type: Option<String>,
+    /// Only replay events whose source starts with this prefix.
+    #[arg(long)]
+    pub source: Option<String>,
+    /// Start of the time range, in RFC 3339 format.
+    #[arg(long)]
+    pub from: Option<DateTime<Utc>>,
+    /// End of the time range, in RFC 3339 format.
+    #[arg(long)]
+    pub to: Option<DateTime<Utc>>,
+    /// Print what would be saved and which sanctions would be taken,
+    /// without saving or sending anything.
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// Run `signaly migrate`.
+pub async fn migrate(
+    scylla: &Manager,
+    dry_run: bool,
+    action: Option<MigrateAction>,
+) -> Result<(), Error> {
+    match action {
+        Some(MigrateAction::Status) => {
+            for status in scylla.migration_status().await? {
+                print_status(&status);
+            }
+        },
+        None if dry_run => {
+            let pending: Vec<_> = scylla
+                .migration_status()
+                .await?
+                .into_iter()
+                .filter(|status| status.applied_at.is_none())
+                .collect();
+
+            if pending.is_empty() {
+                println!("Schema is up to date.");
+            }
+
+            for status in pending {
+                println!(
+                    "-- Migration {}: {}",
+                    status.migration.version, status.migration.description
+                );
+                for step in status.migration.steps {
+                    println!("{}", step);
+                }
+                println!();
+            }
+        },
+        None => {
+            let applied = scylla.migrate().await?;
+
+            if applied.is_empty() {
+                println!("Schema is up to date.");
+            }
+
+            for migration in applied {
+                println!(
+                    "Applied migration {}: {}",
+                    migration.version, migration.description
+                );
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Run `signaly config check`.
+pub fn check_config(config: Result<Config, ConfigError>) -> Result<(), Error> {
+    match config {
+        Ok(config) => {
+            print!("{}", config.redacted().to_toml());
+            Ok(())
+        },
+        Err(error) => {
+            for error in error.0 {
+                eprintln!("error: {}", error);
+            }
+            std::process::exit(1);
+        },
+    }
+}
+
+fn print_status(status: &MigrationStatus) {
+    let state = match status.applied_at {
+        Some(applied_at) => format!("applied {}", applied_at.to_rfc3339()),
+        None => "pending".to_string(),
+    };
+
+    println!(
+        "{:>4}  {:<34}  {}",
+        status.migration.version, state, status.migration.description
+    );
+}
@@ -1,93 +1,267 @@
 //! utils functions to perform global actions.
 
+use std::sync::Arc;
+use std::time::Duration;
+
 use tokio::task;
 use tracing::{error, info, trace};
 
-/// Receive messages from Kafka.
-#[cfg(feature = "kafka")]
-pub fn consume_messages(mut conn: signaly_db::kafka::Consumer) {
-    info!("Listening to incoming messages via Kafka.");
+use signaly_db::broker::{Acknowledgement, Delivery, Publisher, Subscriber};
+use signaly_db::storage::Storage;
+
+use crate::broker::{DeadLetterQueue, Origin};
+use crate::pipeline::Pipeline;
+
+/// First delay before retrying a failed operation.
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+/// Longest delay before retrying a failed operation.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Check the brokers on which messages are published every 30 seconds,
+/// and expose the result in `broker_up` metric.
+#[cfg(feature = "telemetry")]
+pub fn watch_broker_health<P: Publisher + 'static>(publishers: Arc<[P]>) {
+    use signaly_telemetry::metrics::BROKER_HEALTH;
+    use tracing::warn;
 
     task::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(30));
+
         loop {
-            if let Ok(mss) = conn.poll() {
-                for ms in mss.iter() {
-                    for message in ms.messages() {
-                        trace!(
-                            topic = ms.topic(),
-                            partition = ms.partition(),
-                            offset = message.offset,
-                            "Received message."
-                        );
+            interval.tick().await;
 
-                        match String::from_utf8(message.value.to_vec()) {
-                            Ok(string) => {
-                                if let Ok(_v) = serde_json::from_str::<
-                                    crate::models::Event,
-                                >(
-                                    &string
-                                ) {
-                                    // todo: process v.
-                                }
-                            },
-                            Err(_) => {
-                                error!("Message was NOT encoded with UTF-8.")
-                            },
-                        }
-                    }
-                    let _ = conn.consume_messageset(ms);
+            for broker in publishers.iter() {
+                let healthy = broker.check_health().await;
+                if !healthy {
+                    warn!(
+                        broker = broker.name(),
+                        "Broker health check failed."
+                    );
                 }
 
-                let _ = conn.commit_consumed();
+                BROKER_HEALTH
+                    .with_label_values(&[broker.name()])
+                    .set(healthy as i64);
             }
         }
     });
 }
 
-/// Receive messages from RabbitMQ.
-#[cfg(feature = "rabbitmq")]
-pub fn consume_messages(conn: signaly_db::rabbitmq::Manager, topic: String) {
-    use futures_lite::stream::StreamExt;
-    use signaly_db::rabbitmq::{
-        BasicAckOptions, BasicConsumeOptions, FieldTable,
-    };
-
-    info!("Listening to incoming messages via RabbitMQ.");
-
+/// Receive messages from `subscriber` and process them with `pipeline`.
+///
+/// Messages which cannot be processed are sent to `dead_letters`, the
+/// queue of the same broker, if set.
+///
+/// Partitions of a batch are processed concurrently, messages of a
+/// partition in order. A message is only acknowledged once its event has
+/// been saved. Otherwise:
+/// - if the broker can requeue messages, transient errors such as an
+///   unavailable database requeue the message after an exponential backoff,
+///   and other errors reject it;
+/// - else, the message is retried with an exponential backoff instead of
+///   being skipped.
+///
+/// If messages cannot be received, the subscriber reconnects with an
+/// exponential backoff.
+pub fn consume_messages<T, S, P>(
+    mut subscriber: T,
+    pipeline: Arc<Pipeline<S, P>>,
+    dead_letters: Option<DeadLetterQueue<P>>,
+) where
+    T: Subscriber,
+    S: Storage + 'static,
+    P: Publisher + 'static,
+{
     task::spawn(async move {
-        let channel = conn
-            .session
-            .get()
-            .await
-            .unwrap()
-            .create_channel()
-            .await
-            .unwrap();
-        let mut consumer = channel
-            .basic_consume(
-                &topic,
-                "signaly",
-                BasicConsumeOptions::default(),
-                FieldTable::default(),
-            )
-            .await
-            .unwrap();
+        let broker = subscriber.name();
+        let dead_letters = dead_letters.map(Arc::new);
+        info!(broker = broker, "Listening to incoming messages.");
+
+        let mut backoff = MIN_BACKOFF;
+        let mut requeue_backoff = MIN_BACKOFF;
+        let mut disconnected = false;
 
         loop {
-            if let Some(delivery) = consumer.next().await {
-                trace!(topic = topic, "Received message.");
-    
-                if let Ok(message) = delivery {
-                    if let Ok(_v) = serde_json::from_slice::<crate::models::Event>(
-                        &message.data,
-                    ) {
-                        // todo: process v.
-                    }
-                    message.ack(BasicAckOptions::default()).await.expect("ack");
+            let deliveries = match subscriber.receive().await {
+                Ok(deliveries) => deliveries,
+                Err(err) => {
+                    error!(
+                        error = err.to_string(),
+                        broker = broker,
+                        "Messages could not be received, retrying in {:?}.",
+                        backoff
+                    );
+                    disconnected = true;
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    continue;
+                },
+            };
+
+            if disconnected {
+                #[cfg(feature = "telemetry")]
+                signaly_telemetry::metrics::BROKER_RECONNECTIONS
+                    .with_label_values(&[broker])
+                    .inc();
+                info!(broker = broker, "Reconnected to broker.");
+
+                disconnected = false;
+                backoff = MIN_BACKOFF;
+            }
+
+            // Group messages by partition, keeping their order.
+            let mut partitions: Vec<Vec<Delivery>> = Vec::new();
+            for delivery in deliveries {
+                match partitions.iter_mut().find(|partition| {
+                    partition[0].origin.topic == delivery.origin.topic
+                        && partition[0].origin.partition
+                            == delivery.origin.partition
+                }) {
+                    Some(partition) => partition.push(delivery),
+                    None => partitions.push(vec![delivery]),
+                }
+            }
+
+            let handles: Vec<_> = partitions
+                .into_iter()
+                .map(|deliveries| {
+                    let origins: Vec<Origin> = deliveries
+                        .iter()
+                        .map(|delivery| delivery.origin.clone())
+                        .collect();
+                    let handle = task::spawn(consume_partition(
+                        Arc::clone(&pipeline),
+                        dead_letters.clone(),
+                        deliveries,
+                        T::REQUEUES,
+                    ));
+                    (origins, handle)
+                })
+                .collect();
+
+            for (origins, handle) in handles {
+                let acknowledgements = match handle.await {
+                    Ok(acknowledgements) => acknowledgements,
+                    Err(err) => {
+                        error!(
+                            error = err.to_string(),
+                            broker = broker,
+                            "Partition could not be processed, its messages are requeued."
+                        );
+                        origins
+                            .into_iter()
+                            .map(|origin| (origin, Acknowledgement::Requeue))
+                            .collect()
+                    },
+                };
+
+                // Give failed messages back after a delay, so an
+                // unavailable database is not hammered.
+                if acknowledgements.iter().any(|(_, acknowledgement)| {
+                    *acknowledgement == Acknowledgement::Requeue
+                }) {
+                    tokio::time::sleep(requeue_backoff).await;
+                    requeue_backoff = (requeue_backoff * 2).min(MAX_BACKOFF);
                 } else {
-                    error!("RabbitMQ message cannot be decoded.");
+                    requeue_backoff = MIN_BACKOFF;
+                }
+
+                for (origin, acknowledgement) in acknowledgements {
+                    if let Err(err) =
+                        subscriber.acknowledge(&origin, acknowledgement).await
+                    {
+                        error!(
+                            error = err.to_string(),
+                            broker = broker,
+                            topic = origin.topic,
+                            partition = origin.partition,
+                            offset = origin.offset,
+                            delivery_tag = origin.delivery_tag,
+                            "Message could not be acknowledged."
+                        );
+                    }
                 }
             }
         }
     });
 }
+
+/// Process the messages of a partition in order.
+async fn consume_partition<S: Storage, P: Publisher>(
+    pipeline: Arc<Pipeline<S, P>>,
+    dead_letters: Option<Arc<DeadLetterQueue<P>>>,
+    deliveries: Vec<Delivery>,
+    requeues: bool,
+) -> Vec<(Origin, Acknowledgement)> {
+    let mut acknowledgements = Vec::with_capacity(deliveries.len());
+
+    for Delivery { origin, payload } in deliveries {
+        trace!(
+            topic = origin.topic,
+            partition = origin.partition,
+            offset = origin.offset,
+            delivery_tag = origin.delivery_tag,
+            "Received message."
+        );
+
+        let mut backoff = MIN_BACKOFF;
+
+        let acknowledgement = loop {
+            let err = match pipeline
+                .consume(&payload, &origin, dead_letters.as_deref())
+                .await
+            {
+                Ok(()) => break Acknowledgement::Ack,
+                Err(err) => err,
+            };
+
+            if !requeues {
+                error!(
+                    error = err.to_string(),
+                    topic = origin.topic,
+                    partition = origin.partition,
+                    offset = origin.offset,
+                    "Event could not be saved, retrying in {:?}.",
+                    backoff
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            } else if is_transient(&err) {
+                error!(
+                    error = err.to_string(),
+                    topic = origin.topic,
+                    delivery_tag = origin.delivery_tag,
+                    "Event could not be saved, requeuing it."
+                );
+                break Acknowledgement::Requeue;
+            } else {
+                error!(
+                    error = err.to_string(),
+                    topic = origin.topic,
+                    delivery_tag = origin.delivery_tag,
+                    "Event cannot be processed, rejecting it."
+                );
+                break Acknowledgement::Reject;
+            }
+        };
+
+        acknowledgements.push((origin, acknowledgement));
+    }
+
+    acknowledgements
+}
+
+/// Whether `error` may not happen on a new attempt, such as an unavailable
+/// database.
+fn is_transient(error: &signaly_error::Error) -> bool {
+    use signaly_error::{DatabaseError, ErrorType};
+
+    matches!(
+        error.etype,
+        ErrorType::Database(
+            DatabaseError::PoolObtention
+                | DatabaseError::QueryFailed
+                | DatabaseError::MessageNotSent
+        ) | ErrorType::InuputOutput(_)
+    )
+}
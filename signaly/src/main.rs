@@ -1,14 +1,365 @@
 //! Report and sanction aggregator to perform targeted research.
+// Without a broker, messages are never consumed.
+#![cfg_attr(
+    not(any(feature = "kafka", feature = "rabbitmq")),
+    allow(dead_code, unused_variables)
+)]
+mod broker;
+mod cli;
+mod config;
 mod helpers;
 mod models;
+mod pipeline;
+mod replay;
 mod router;
+mod sanction;
+#[cfg(test)]
+mod tests;
+mod validation;
 
+use std::sync::Arc;
+
+use broker::{DeadLetterQueue, Publisher};
+use clap::Parser;
+use cli::{Cli, Command, ConfigAction};
+use config::Config;
+use pipeline::Pipeline;
+use sanction::{
+    lifecycle::{self, Lifecycle},
+    Engine, Policy,
+};
 use signaly_db::cassandra::Manager as ScyllaManager;
-use tracing::{error, Level};
+use tracing::{error, info, warn, Level};
 use tracing_subscriber::fmt;
 
+/// Start the API, the expiry of sanctions and the broker health checks,
+/// then create the pipeline used by consumers.
+fn start(
+    scylla: Arc<ScyllaManager>,
+    engine: Arc<Engine>,
+    publishers: Vec<Publisher>,
+    sanction_topic: String,
+    api: config::ApiConfig,
+) -> Arc<Pipeline<ScyllaManager, Publisher>> {
+    let publishers: Arc<[Publisher]> = publishers.into();
+    let lifecycle = Arc::new(Lifecycle::new(
+        Arc::clone(&scylla),
+        Arc::clone(&publishers),
+        sanction_topic.clone(),
+    ));
+
+    lifecycle::watch_expiry(Arc::clone(&lifecycle));
+    #[cfg(feature = "telemetry")]
+    helpers::watch_broker_health(Arc::clone(&publishers));
+    tokio::spawn(router::serve(
+        Arc::clone(&scylla),
+        lifecycle,
+        api.port,
+        api.admin_token,
+    ));
+
+    Arc::new(Pipeline::new(scylla, engine, publishers, sanction_topic))
+}
+
+/// Run `signaly replay` on the dead letter queues of `dead_letter`, or on
+/// `dead_letters` table if none is set.
+///
+/// Dry runs send nothing, so they only connect to brokers to read their
+/// dead letter queue.
+async fn replay(
+    config: &Config,
+    scylla: Arc<ScyllaManager>,
+    engine: Arc<Engine>,
+    args: &cli::ReplayArgs,
+) -> signaly_error::Result<()> {
+    let kafka_queue = cfg!(feature = "kafka")
+        && !config.kafka.brokers.is_empty()
+        && config.dead_letter.topic.is_some();
+    let rabbitmq_queue = cfg!(feature = "rabbitmq")
+        && config.amqp.broker.is_some()
+        && config.dead_letter.queue.is_some();
+    let from_queues = args.file.is_none() && (kafka_queue || rabbitmq_queue);
+
+    let brokers = if args.dry_run && !from_queues {
+        Brokers::default()
+    } else {
+        Brokers::connect(config).await?
+    };
+    let publishers = if args.dry_run {
+        Vec::new()
+    } else {
+        brokers.publishers(config).await?
+    };
+    let pipeline = Pipeline::new(
+        Arc::clone(&scylla),
+        engine,
+        publishers.into(),
+        config.sanction.topic.clone(),
+    );
+
+    if !from_queues {
+        let summary = replay::run(&pipeline, &scylla, args).await?;
+        println!("{}.", summary);
+        return Ok(());
+    }
+
+    #[cfg(feature = "kafka")]
+    if let (Some(hosts), Some(topic)) =
+        (&brokers.kafka, &config.dead_letter.topic)
+    {
+        let mut consumer = config.kafka.consumer();
+        consumer.topics = vec![topic.clone()];
+        consumer.group = format!("{}-replay", consumer.group);
+        // Dead letters older than the group are read too.
+        consumer.fallback_offset = signaly_db::kafka::FetchOffset::Earliest;
+        let subscriber = signaly_db::kafka::KafkaSubscriber::new(
+            signaly_db::kafka::new_consumer(hosts.clone(), consumer).await?,
+        );
+
+        let dead_letters = match args.dry_run {
+            true => None,
+            false => brokers.dead_letter_queue("kafka", config).await?,
+        };
+        let summary = replay::run_queue(
+            &pipeline,
+            subscriber,
+            dead_letters.as_ref(),
+            args,
+        )
+        .await?;
+        println!("{}: {}.", topic, summary);
+    }
+
+    #[cfg(feature = "rabbitmq")]
+    if let (Some(manager), Some(queue)) =
+        (&brokers.rabbitmq, &config.dead_letter.queue)
+    {
+        let subscriber = signaly_db::rabbitmq::RabbitMqSubscriber::new(
+            manager.clone(),
+            signaly_db::rabbitmq::ConsumerConfig {
+                queue: queue.clone(),
+                tag: format!("signaly-replay-{}", uuid::Uuid::new_v4()),
+                // Dry runs acknowledge nothing, so RabbitMQ would stop
+                // delivering after `prefetch` dead letters.
+                prefetch: match args.dry_run {
+                    true => 0,
+                    false => config.amqp.prefetch,
+                },
+            },
+        );
+
+        let dead_letters = match args.dry_run {
+            true => None,
+            false => brokers.dead_letter_queue("rabbitmq", config).await?,
+        };
+        let summary = replay::run_queue(
+            &pipeline,
+            subscriber,
+            dead_letters.as_ref(),
+            args,
+        )
+        .await?;
+        println!("{}: {}.", queue, summary);
+    }
+
+    Ok(())
+}
+
+/// Brokers set in the configuration.
+#[derive(Default)]
+struct Brokers {
+    /// Kafka brokers of `kafka.brokers`.
+    #[cfg(feature = "kafka")]
+    kafka: Option<Vec<String>>,
+    /// Connection to the RabbitMQ broker of `amqp.broker`.
+    #[cfg(feature = "rabbitmq")]
+    rabbitmq: Option<signaly_db::rabbitmq::Manager>,
+}
+
+impl Brokers {
+    /// Read brokers from the configuration and connect to RabbitMQ.
+    #[allow(unused_variables)]
+    async fn connect(config: &Config) -> signaly_error::Result<Self> {
+        #[allow(unused_mut)]
+        let mut brokers = Brokers::default();
+
+        #[cfg(feature = "kafka")]
+        if !config.kafka.brokers.is_empty() {
+            brokers.kafka = Some(config.kafka.brokers.clone());
+        }
+
+        #[cfg(feature = "rabbitmq")]
+        if let Some(address) = &config.amqp.broker {
+            brokers.rabbitmq =
+                Some(connect_rabbitmq(address.clone(), &config.amqp).await?);
+        }
+
+        Ok(brokers)
+    }
+
+    /// Names of the brokers set.
+    fn names(&self) -> Vec<&'static str> {
+        #[allow(unused_mut)]
+        let mut names = Vec::new();
+
+        #[cfg(feature = "kafka")]
+        if self.kafka.is_some() {
+            names.push("kafka");
+        }
+
+        #[cfg(feature = "rabbitmq")]
+        if self.rabbitmq.is_some() {
+            names.push("rabbitmq");
+        }
+
+        names
+    }
+
+    /// Create the publishers of the brokers of `sanction.brokers`, every
+    /// broker set by default.
+    async fn publishers(
+        &self,
+        config: &Config,
+    ) -> signaly_error::Result<Vec<Publisher>> {
+        let names: Vec<String> = match &config.sanction.brokers {
+            Some(names) => names.clone(),
+            None => self.names().iter().map(ToString::to_string).collect(),
+        };
+
+        let mut publishers = Vec::new();
+        for name in names {
+            publishers.push(self.broker_publisher(&name, config).await?);
+        }
+
+        if publishers.is_empty() {
+            return Err("No specified broker in configuration (kafka.brokers or amqp.broker) OR wrong feature built.".into());
+        }
+
+        Ok(publishers)
+    }
+
+    /// Create the publisher of broker `name`.
+    #[allow(unused_variables)]
+    async fn broker_publisher(
+        &self,
+        name: &str,
+        config: &Config,
+    ) -> signaly_error::Result<Publisher> {
+        match name {
+            #[cfg(feature = "kafka")]
+            "kafka" => match &self.kafka {
+                Some(hosts) => Ok(Publisher::Kafka(
+                    signaly_db::kafka::Manager::new(
+                        hosts.clone(),
+                        config.kafka.producer(),
+                    )
+                    .await?,
+                )),
+                None => {
+                    Err("invalid sanction.brokers: kafka.brokers is not set"
+                        .into())
+                },
+            },
+            #[cfg(feature = "rabbitmq")]
+            "rabbitmq" => match &self.rabbitmq {
+                Some(manager) => Ok(Publisher::RabbitMq(
+                    signaly_db::rabbitmq::RabbitMqPublisher::new(
+                        manager.clone(),
+                        config.sanction.exchange.clone(),
+                    ),
+                )),
+                None => {
+                    Err("invalid sanction.brokers: amqp.broker is not set"
+                        .into())
+                },
+            },
+            name => Err(format!(
+                "invalid sanction.brokers: broker {:?} is not built",
+                name
+            )
+            .into()),
+        }
+    }
+}
+
+impl Brokers {
+    /// Create the dead letter queue of broker `name`, if set in
+    /// `dead_letter`.
+    #[allow(unused_variables)]
+    async fn dead_letter_queue(
+        &self,
+        name: &str,
+        config: &Config,
+    ) -> signaly_error::Result<Option<DeadLetterQueue<Publisher>>> {
+        let queue = match name {
+            #[cfg(feature = "kafka")]
+            "kafka" => match (&self.kafka, &config.dead_letter.topic) {
+                (Some(hosts), Some(topic)) => Some(DeadLetterQueue {
+                    publisher: Publisher::Kafka(
+                        signaly_db::kafka::Manager::new(
+                            hosts.clone(),
+                            config.kafka.producer(),
+                        )
+                        .await?,
+                    ),
+                    destination: topic.clone(),
+                }),
+                _ => None,
+            },
+            #[cfg(feature = "rabbitmq")]
+            "rabbitmq" => match (&self.rabbitmq, &config.dead_letter.queue) {
+                // Sent to the queue through the default exchange.
+                (Some(manager), Some(queue)) => Some(DeadLetterQueue {
+                    publisher: Publisher::RabbitMq(
+                        signaly_db::rabbitmq::RabbitMqPublisher::new(
+                            manager.clone(),
+                            None,
+                        ),
+                    ),
+                    destination: queue.clone(),
+                }),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        if queue.is_none() {
+            warn!(
+                broker = name,
+                "No dead letter queue (dead_letter.topic or dead_letter.queue), invalid messages are only saved in Apache Cassandra."
+            );
+        }
+
+        Ok(queue)
+    }
+}
+
+/// Connect to RabbitMQ and declare the topology of `amqp.topology`, if set.
+#[cfg(feature = "rabbitmq")]
+async fn connect_rabbitmq(
+    address: String,
+    config: &config::AmqpConfig,
+) -> signaly_error::Result<signaly_db::rabbitmq::Manager> {
+    let manager = signaly_db::rabbitmq::Manager::new(address).await?;
+
+    if let Some(path) = &config.topology {
+        let topology = broker::topology::from_file(path)?;
+        manager.declare(&topology).await?;
+        info!(
+            exchanges = topology.exchanges.len(),
+            queues = topology.queues.len(),
+            bindings = topology.bindings.len(),
+            "Declared RabbitMQ topology."
+        );
+    }
+
+    Ok(manager)
+}
+
 #[tokio::main]
 async fn main() -> signaly_error::Result<()> {
+    let cli = Cli::parse();
+
     #[cfg(not(debug_assertions))]
     fmt()
         .with_file(true)
@@ -25,24 +376,32 @@ async fn main() -> signaly_error::Result<()> {
         .with_max_level(Level::TRACE)
         .init();
 
+    let config = Config::load(&cli.config);
+    if let Some(Command::Config {
+        action: ConfigAction::Check,
+    }) = cli.command
+    {
+        return cli::check_config(config).map_err(Into::into);
+    }
+
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            error!(
+                target = "signaly",
+                error = err.to_string(),
+                "Invalid configuration, check it with `signaly config check`."
+            );
+            std::process::exit(1);
+        },
+    };
+
     let scylla = match ScyllaManager::new(
-        std::env::var("CASSANDRA_HOSTS")
-            .unwrap_or_else(|_| "127.0.0.1:9042".to_string())
-            .split(',')
-            .map(ToString::to_string)
-            .collect(),
-        Some(
-            std::env::var("CASSANDRA_USERNAME")
-                .unwrap_or_else(|_| "cassandra".to_string()),
-        ),
-        Some(
-            std::env::var("CASSANDRA_PASSWORD")
-                .unwrap_or_else(|_| "cassandra".to_string()),
-        ),
-        std::env::var("CASSANDRA_POOL_SIZE")
-            .unwrap_or_else(|_| "10".to_string())
-            .parse()
-            .unwrap_or(10),
+        config.cassandra.hosts.clone(),
+        Some(config.cassandra.username.clone()),
+        Some(config.cassandra.password.clone()),
+        config.cassandra.pool_size,
+        config.cassandra.keyspace()?,
     )
     .await
     {
@@ -57,53 +416,99 @@ async fn main() -> signaly_error::Result<()> {
         },
     };
 
-    scylla.create_tables().await?;
-
-    match (std::env::var("KAFKA_BROKERS"), std::env::var("AMQP_BROKER")) {
-        #[cfg(feature = "kafka")]
-        (Ok(brokers), Err(_)) => {
-            use signaly_db::kafka::{new_consumer, Manager as KafkaManager};
-
-            let kafka_hosts: Vec<String> =
-                brokers.split(',').map(ToString::to_string).collect();
-
-            let kafka_producer = KafkaManager::new(
-                kafka_hosts.clone(),
-                std::env::var("KAFKA_POOL_SIZE")
-                    .unwrap_or_else(|_| "5".to_string())
-                    .parse()
-                    .unwrap_or(5),
-            )
-            .await?;
+    let command = match cli.command {
+        Some(Command::Migrate { dry_run, action }) => {
+            cli::migrate(&scylla, dry_run, action).await?;
+            return Ok(());
+        },
+        command => command,
+    };
 
-            let kafka_consumer = new_consumer(
-                std::env::var("TOPIC").unwrap_or_else(|_| "*".to_string()),
-                kafka_hosts,
-            )
-            .await?;
+    // Migrations may drop data, so they are only applied on demand.
+    let pending = scylla
+        .migration_status()
+        .await?
+        .into_iter()
+        .filter(|status| status.applied_at.is_none())
+        .map(|status| status.migration.version.to_string())
+        .collect::<Vec<_>>();
+    if !pending.is_empty() {
+        error!(
+            target = "signaly",
+            pending = pending.join(", "),
+            "Schema migrations are pending, apply them with `signaly migrate`."
+        );
+        std::process::exit(1);
+    }
 
-            helpers::consume_messages(kafka_consumer);
+    let scylla = Arc::new(scylla);
 
-            #[cfg(not(feature = "telemetry"))]
-            loop {}
+    let engine = match &config.sanction.policy {
+        Some(path) => {
+            let engine = Arc::new(Engine::new(Policy::from_file(path)?));
+            sanction::policy::watch(path.clone(), Arc::clone(&engine));
+            engine
         },
-        #[cfg(feature = "rabbitmq")]
-        (Err(_), Ok(address)) => {
-            use signaly_db::rabbitmq::Manager as LapinManger;
+        None => {
+            info!("No sanction policy (sanction.policy), automatic sanctions are disabled.");
+            Arc::new(Engine::default())
+        },
+    };
+    if let Some(Command::Replay(args)) = command {
+        return replay(&config, scylla, engine, &args).await;
+    }
 
-            let rabbitmq = LapinManger::new(address).await?;
-            helpers::consume_messages(
-                rabbitmq,
-                std::env::var("TOPIC").unwrap_or_else(|_| "*".to_string()),
-            );
+    let sanction_topic = config.sanction.topic.clone();
+    let brokers = Brokers::connect(&config).await?;
 
-            #[cfg(not(feature = "telemetry"))]
-            loop {}
-        },
-        _ => {
-            error!("No specified broker in environment (KAFKA_BROKERS or AMQP_BROKER) OR wrong feature built.");
-            std::process::exit(0);
-        },
+    if brokers.names().is_empty() {
+        error!("No specified broker in configuration (kafka.brokers or amqp.broker) OR wrong feature built.");
+        std::process::exit(0);
+    }
+
+    let pipeline = start(
+        scylla,
+        engine,
+        brokers.publishers(&config).await?,
+        sanction_topic,
+        config.api.clone(),
+    );
+
+    #[cfg(feature = "kafka")]
+    if let Some(hosts) = &brokers.kafka {
+        let consumer = signaly_db::kafka::new_consumer(
+            hosts.clone(),
+            config.kafka.consumer(),
+        )
+        .await?;
+
+        helpers::consume_messages(
+            signaly_db::kafka::KafkaSubscriber::new(consumer),
+            Arc::clone(&pipeline),
+            brokers.dead_letter_queue("kafka", &config).await?,
+        );
+    }
+
+    #[cfg(feature = "rabbitmq")]
+    if let Some(manager) = &brokers.rabbitmq {
+        helpers::consume_messages(
+            signaly_db::rabbitmq::RabbitMqSubscriber::new(
+                manager.clone(),
+                signaly_db::rabbitmq::ConsumerConfig {
+                    queue: config.amqp.queue.clone(),
+                    tag: format!("signaly-{}", uuid::Uuid::new_v4()),
+                    prefetch: config.amqp.prefetch,
+                },
+            ),
+            Arc::clone(&pipeline),
+            brokers.dead_letter_queue("rabbitmq", &config).await?,
+        );
+    }
+
+    #[cfg(not(feature = "telemetry"))]
+    {
+        std::future::pending::<()>().await;
+        Ok(())
     }
 
     #[cfg(feature = "telemetry")]
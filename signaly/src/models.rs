@@ -1,9 +1,12 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use signaly_db::broker::Origin;
 
 /// Cloudevents structure.
 #[derive(Serialize, Deserialize, Debug)]
-pub struct Event
-{
+pub struct Event<T = Data> {
     /// CloudEvents specification version.
     pub specversion: String,
     /// Type of the event.
@@ -13,12 +16,15 @@ pub struct Event
     /// Unique identifier for the event.
     /// Will be used to save in database.
     pub id: String,
-    /// Timestamp of when the event occurred in RFC 3339 format.
+    /// Timestamp of when the event occurred in RFC 3339 format, empty if
+    /// not set.
+    #[serde(default)]
     pub time: String,
-    /// Must be application/json.
+    /// Must be application/json, empty if not set.
+    #[serde(default)]
     pub datacontenttype: String,
     /// Data associated with the event.
-    pub data: Data,
+    pub data: T,
 }
 
 /// Data transmitted by the broker.
@@ -28,28 +34,119 @@ pub struct Data {
     pub from: String,
     /// Vanity of the user affected by the sanction or report.
     pub to: String,
-    /// Reason for the sanction or warning to be recorded. 
+    /// Reason for the sanction or warning to be recorded.
     pub reason: Reason,
     /// Defines message processing.
     #[serde(default)]
     pub r#type: Type,
     /// Sanction taken against user.
     pub sanction: Option<Sanction>,
+    /// End of the sanction in RFC 3339 format, permanent if not set.
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
+/// Data of a sanction produced by Signaly.
 #[derive(Serialize, Deserialize, Debug)]
-pub enum Type {
-    Report,
-    Sanction,
+pub struct SanctionData {
+    /// Vanity of the user or content sanctioned.
+    pub to: String,
+    /// Predetermined name of the reason.
+    pub reason: String,
+    /// Sanction taken against user.
+    pub sanction: Sanction,
+    /// End of the sanction, permanent if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
-impl Default for Type {
-    fn default() -> Self {
-        Self::Report
+/// Data of a change of sanction status produced by Signaly.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StatusData {
+    /// Unique identifier of the sanction.
+    pub id: String,
+    /// Vanity of the user or content sanctioned.
+    pub to: String,
+    /// Predetermined name of the reason.
+    pub reason: String,
+    /// Sanction taken against user.
+    pub sanction: Option<Sanction>,
+    /// New status of the sanction.
+    pub status: Status,
+}
+
+/// Message sent to a dead letter queue, wrapping a received message which
+/// cannot be processed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeadLetterEnvelope {
+    /// Received message, if valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+    /// Received message in hexadecimal, if not valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_hex: Option<String>,
+    /// Why the message cannot be processed.
+    pub error: String,
+    /// Time the message was rejected.
+    pub failed_at: DateTime<Utc>,
+    /// Kafka topic or RabbitMQ queue the message was received from.
+    pub topic: String,
+    /// Kafka partition.
+    pub partition: Option<i32>,
+    /// Kafka offset.
+    pub offset: Option<i64>,
+    /// RabbitMQ delivery tag.
+    pub delivery_tag: Option<u64>,
+    /// Time a replay last sent the message back, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replayed_at: Option<DateTime<Utc>>,
+}
+
+impl DeadLetterEnvelope {
+    /// Wrap `payload`, received at `origin`, rejected because of `error`.
+    pub fn new(
+        payload: &[u8],
+        origin: &Origin,
+        error: String,
+        failed_at: DateTime<Utc>,
+    ) -> Self {
+        let (payload, payload_hex) = match std::str::from_utf8(payload) {
+            Ok(payload) => (Some(payload.to_string()), None),
+            Err(_) => (None, Some(hex::encode(payload))),
+        };
+
+        DeadLetterEnvelope {
+            payload,
+            payload_hex,
+            error,
+            failed_at,
+            topic: origin.topic.clone(),
+            partition: origin.partition,
+            offset: origin.offset,
+            delivery_tag: origin.delivery_tag,
+            replayed_at: None,
+        }
+    }
+
+    /// Received message.
+    pub fn payload(&self) -> Result<Vec<u8>, String> {
+        match (&self.payload, &self.payload_hex) {
+            (Some(payload), _) => Ok(payload.clone().into_bytes()),
+            (None, Some(payload)) => hex::decode(payload)
+                .map_err(|error| format!("payload_hex: {}", error)),
+            (None, None) => Err("payload is required".to_string()),
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum Type {
+    #[default]
+    Report,
+    Sanction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[repr(u8)]
 pub enum Reason {
     Copyright = 0,
@@ -62,7 +159,83 @@ pub enum Reason {
     Other(String) = 7,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Reason {
+    /// Get a reason from its numeric code saved in database.
+    pub fn from_code(code: i32, text: Option<String>) -> Option<Self> {
+        match code {
+            0 => Some(Reason::Copyright),
+            1 => Some(Reason::Defamation),
+            2 => Some(Reason::Hate),
+            3 => Some(Reason::Harassment),
+            4 => Some(Reason::Nudity),
+            5 => Some(Reason::Spam),
+            6 => Some(Reason::Violence),
+            7 => Some(Reason::Other(text.unwrap_or_default())),
+            _ => None,
+        }
+    }
+
+    /// Predetermined name of the reason with numeric `code`.
+    pub fn name_of(code: i32) -> &'static str {
+        Reason::from_code(code, None).map_or("Unknown", |reason| reason.name())
+    }
+
+    /// Numeric code saved in database.
+    pub fn code(&self) -> i32 {
+        match self {
+            Reason::Copyright => 0,
+            Reason::Defamation => 1,
+            Reason::Hate => 2,
+            Reason::Harassment => 3,
+            Reason::Nudity => 4,
+            Reason::Spam => 5,
+            Reason::Violence => 6,
+            Reason::Other(_) => 7,
+        }
+    }
+
+    /// Predetermined name of the reason.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Reason::Copyright => "Copyright",
+            Reason::Defamation => "Defamation",
+            Reason::Hate => "Hate",
+            Reason::Harassment => "Harassment",
+            Reason::Nudity => "Nudity",
+            Reason::Spam => "Spam",
+            Reason::Violence => "Violence",
+            Reason::Other(_) => "Other",
+        }
+    }
+
+    /// Free text given with [`Reason::Other`].
+    pub fn text(&self) -> Option<String> {
+        match self {
+            Reason::Other(text) => Some(text.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Reason {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "Copyright" => Ok(Reason::Copyright),
+            "Defamation" => Ok(Reason::Defamation),
+            "Hate" => Ok(Reason::Hate),
+            "Harassment" => Ok(Reason::Harassment),
+            "Nudity" => Ok(Reason::Nudity),
+            "Spam" => Ok(Reason::Spam),
+            "Violence" => Ok(Reason::Violence),
+            "Other" => Ok(Reason::Other(String::new())),
+            _ => Err(format!("unknown reason {:?}", name)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 #[repr(u8)]
 pub enum Sanction {
     /// The user no longer has access to services.
@@ -70,3 +243,94 @@ pub enum Sanction {
     /// Content (publication, comment, etc.) is permanently removed.
     Removal = 1,
 }
+
+impl Sanction {
+    /// Get a sanction from its numeric code saved in database.
+    pub fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(Sanction::Suspension),
+            1 => Some(Sanction::Removal),
+            _ => None,
+        }
+    }
+
+    /// Numeric code saved in database.
+    pub fn code(&self) -> i32 {
+        match self {
+            Sanction::Suspension => 0,
+            Sanction::Removal => 1,
+        }
+    }
+
+    /// Predetermined name of the sanction.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sanction::Suspension => "Suspension",
+            Sanction::Removal => "Removal",
+        }
+    }
+
+    /// Name of the sanction in RabbitMQ routing keys.
+    pub fn routing_name(&self) -> &'static str {
+        match self {
+            Sanction::Suspension => "suspension",
+            Sanction::Removal => "removal",
+        }
+    }
+}
+
+impl FromStr for Sanction {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "Suspension" => Ok(Sanction::Suspension),
+            "Removal" => Ok(Sanction::Removal),
+            _ => Err(format!("unknown sanction {:?}", name)),
+        }
+    }
+}
+
+/// Lifecycle of a sanction.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum Status {
+    /// The sanction applies.
+    Active = 0,
+    /// The end of the sanction has passed.
+    Expired = 1,
+    /// The sanction has been lifted by a moderator.
+    Revoked = 2,
+    /// The sanctioned user contests the sanction.
+    Appealed = 3,
+}
+
+impl Status {
+    /// Get a status from its numeric code saved in database.
+    ///
+    /// Sanctions saved without status are active.
+    pub fn from_code(code: Option<i32>) -> Option<Self> {
+        match code {
+            None | Some(0) => Some(Status::Active),
+            Some(1) => Some(Status::Expired),
+            Some(2) => Some(Status::Revoked),
+            Some(3) => Some(Status::Appealed),
+            _ => None,
+        }
+    }
+
+    /// Numeric code saved in database.
+    pub fn code(&self) -> i32 {
+        *self as i32
+    }
+
+    /// Lowercase name used in CloudEvents types.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Active => "active",
+            Status::Expired => "expired",
+            Status::Revoked => "revoked",
+            Status::Appealed => "appealed",
+        }
+    }
+}
@@ -0,0 +1,488 @@
+//! Processing applied to every event received from a broker.
+
+use chrono::{DateTime, Utc};
+use signaly_db::broker::Publisher;
+use signaly_db::storage::{DeadLetter, Report, Sanction, Storage};
+use signaly_error::{Error, ErrorType};
+use tracing::{debug, info, trace, warn};
+use uuid::Uuid;
+
+use std::{collections::HashSet, sync::Arc};
+
+use crate::broker::{deliver, DeadLetterQueue, Message, Origin};
+use crate::models::{DeadLetterEnvelope, Event, SanctionData, Status, Type};
+use crate::sanction::{sanction_event, Engine, Rule, SANCTION_SOURCE};
+use crate::validation::{self, Rejection};
+
+/// Shared state used by consumers to process events.
+///
+/// Events are saved in `S`, sanctions are published on every broker `P`.
+#[allow(missing_debug_implementations)]
+pub struct Pipeline<S, P> {
+    storage: Arc<S>,
+    engine: Arc<Engine>,
+    publishers: Arc<[P]>,
+    sanction_topic: String,
+}
+
+impl<S: Storage, P: Publisher> Pipeline<S, P> {
+    /// Create a new [`Pipeline`].
+    ///
+    /// Sanctions taken by `engine` are sent to `sanction_topic`.
+    pub fn new(
+        storage: Arc<S>,
+        engine: Arc<Engine>,
+        publishers: Arc<[P]>,
+        sanction_topic: String,
+    ) -> Self {
+        Pipeline {
+            storage,
+            engine,
+            publishers,
+            sanction_topic,
+        }
+    }
+
+    /// Validate a message received from a broker, then process it.
+    ///
+    /// Undecodable and invalid messages are sent to `dead_letters`, if any,
+    /// and saved in `dead_letters` table with their `origin` instead of
+    /// being dropped. Consumers must only acknowledge the message once this
+    /// returns `Ok`.
+    pub async fn consume(
+        &self,
+        payload: &[u8],
+        origin: &Origin,
+        dead_letters: Option<&DeadLetterQueue<P>>,
+    ) -> Result<(), Error> {
+        match validation::parse(payload) {
+            Ok(event) => self.process(&event).await.map(|_| ()),
+            Err(rejection) => {
+                self.reject(payload, origin, &rejection, dead_letters).await
+            },
+        }
+    }
+
+    /// Send an invalid message to `dead_letters`, then save it in
+    /// `dead_letters` table.
+    ///
+    /// The table is only an index of the queue: if the message has been
+    /// sent to the queue, failing to save it is not an error.
+    async fn reject(
+        &self,
+        payload: &[u8],
+        origin: &Origin,
+        rejection: &Rejection,
+        dead_letters: Option<&DeadLetterQueue<P>>,
+    ) -> Result<(), Error> {
+        let failed_at = Utc::now();
+        let letter = DeadLetter {
+            id: Uuid::new_v4(),
+            received_at: failed_at,
+            payload: payload.to_vec(),
+            error: rejection.to_string(),
+            topic: Some(origin.topic.clone()),
+            partition: origin.partition,
+            offset: origin.offset,
+            delivery_tag: origin.delivery_tag.map(|tag| tag as i64),
+        };
+
+        match dead_letters {
+            Some(queue) => {
+                queue
+                    .send(&DeadLetterEnvelope::new(
+                        payload,
+                        origin,
+                        rejection.to_string(),
+                        failed_at,
+                    ))
+                    .await?;
+
+                if let Err(err) = self.storage.insert_dead_letter(&letter).await
+                {
+                    warn!(
+                        error = err.to_string(),
+                        id = letter.id.to_string(),
+                        "Dead letter could not be saved, it is only in the dead letter queue."
+                    );
+                }
+            },
+            None => self.storage.insert_dead_letter(&letter).await?,
+        }
+
+        #[cfg(feature = "telemetry")]
+        for (field, reason) in rejection.labels() {
+            signaly_telemetry::metrics::REJECTIONS_COLLECTOR
+                .with_label_values(&[field, reason])
+                .inc();
+        }
+
+        warn!(
+            error = rejection.to_string(),
+            topic = origin.topic,
+            partition = origin.partition,
+            offset = origin.offset,
+            delivery_tag = origin.delivery_tag,
+            "Invalid message sent to dead letters."
+        );
+
+        Ok(())
+    }
+
+    /// Save an event in its dedicated table, then take automatic sanctions
+    /// if the event trips a rule of the policy.
+    ///
+    /// Events already processed, identified by their `source` and `id`,
+    /// are ignored so that redelivered messages are not counted twice. An
+    /// event is only marked as processed once saved and enforced, so an
+    /// event which failed, even on a crash, is processed again when
+    /// redelivered. Consumers must only acknowledge the message once this
+    /// returns `Ok`.
+    pub async fn process(&self, event: &Event) -> Result<Outcome, Error> {
+        if self
+            .storage
+            .is_event_processed(&event.source, &event.id)
+            .await?
+        {
+            #[cfg(feature = "telemetry")]
+            signaly_telemetry::metrics::DUPLICATES_COLLECTOR
+                .with_label_values(&[&event.source])
+                .inc();
+
+            debug!(
+                id = event.id,
+                source = event.source,
+                "Duplicate event ignored."
+            );
+            return Ok(Outcome::Duplicate);
+        }
+
+        // Saving an event again overwrites the same row.
+        self.save(event).await?;
+        let sanctions = self.enforce(event).await?;
+        self.storage
+            .mark_event_processed(&event.source, &event.id)
+            .await?;
+
+        Ok(Outcome::Accepted { sanctions })
+    }
+
+    /// Get what [`Pipeline::process`] would do with an event, without
+    /// saving or sending anything.
+    ///
+    /// Rules are checked against saved events and those of `simulation`,
+    /// to which `event` and its sanctions are added.
+    pub async fn simulate(
+        &self,
+        event: &Event,
+        simulation: &mut Simulation,
+    ) -> Result<Outcome, Error> {
+        let key = (event.source.clone(), event.id.clone());
+        if simulation.events.contains(&key)
+            || self
+                .storage
+                .is_event_processed(&event.source, &event.id)
+                .await?
+        {
+            return Ok(Outcome::Duplicate);
+        }
+
+        let now = Utc::now();
+        let target = event.data.to.as_str();
+        simulation.events.insert(key);
+        simulation.records.push(record(event));
+
+        let mut sanctions = Vec::new();
+        for rule in self.tripped_rules(event, &simulation.records, now).await? {
+            let id = sanction_id(event, &rule);
+            let message = sanction_event(id, target, &rule, now);
+
+            simulation.records.push(Record::Sanction(automatic_sanction(
+                id, target, &rule, &message, now,
+            )));
+            sanctions.push(message);
+        }
+
+        Ok(Outcome::Accepted { sanctions })
+    }
+
+    /// Save an event in its dedicated table.
+    async fn save(&self, event: &Event) -> Result<(), Error> {
+        match record(event) {
+            Record::Report(report) => {
+                self.storage.insert_report(&report).await?;
+
+                #[cfg(feature = "telemetry")]
+                signaly_telemetry::metrics::REPORTS_COLLECTOR
+                    .with_label_values(&[
+                        &event.source,
+                        event.data.reason.name(),
+                    ])
+                    .inc();
+            },
+            Record::Sanction(sanction) => {
+                self.storage.insert_sanction(&sanction).await?;
+
+                #[cfg(feature = "telemetry")]
+                signaly_telemetry::metrics::SANCTIONS_COLLECTOR
+                    .with_label_values(&[
+                        event.data.reason.name(),
+                        &event.data.from,
+                        event
+                            .data
+                            .sanction
+                            .as_ref()
+                            .map_or("", |sanction| sanction.name()),
+                    ])
+                    .inc();
+            },
+        }
+
+        trace!(id = event.id, source = event.source, "Event saved.");
+
+        Ok(())
+    }
+
+    /// Take sanctions against the target of `event` if it trips a rule of
+    /// the policy.
+    async fn enforce(
+        &self,
+        event: &Event,
+    ) -> Result<Vec<Event<SanctionData>>, Error> {
+        let now = Utc::now();
+        let target = event.data.to.as_str();
+        let mut taken = Vec::new();
+
+        for rule in self.tripped_rules(event, &[], now).await? {
+            // A retried event takes the same sanction, announced by a
+            // message with the same identifier.
+            let id = sanction_id(event, &rule);
+            let message = sanction_event(id, target, &rule, now);
+
+            let content = serde_json::to_string(&message).map_err(|error| {
+                Error::new(
+                    ErrorType::Unspecified,
+                    Some(Box::new(error)),
+                    Some("while serializing a sanction".to_string()),
+                )
+            })?;
+            deliver(
+                &*self.storage,
+                &self.publishers,
+                id,
+                &self.sanction_topic,
+                Message {
+                    key: Some(target),
+                    routing_key: format!(
+                        "sanction.{}",
+                        rule.sanction.routing_name()
+                    ),
+                    content,
+                },
+            )
+            .await?;
+
+            // Only saved once announced, so an unsaved sanction is
+            // announced again when the event is retried.
+            self.storage
+                .insert_sanction(&automatic_sanction(
+                    id, target, &rule, &message, now,
+                ))
+                .await?;
+
+            #[cfg(feature = "telemetry")]
+            signaly_telemetry::metrics::SANCTIONS_COLLECTOR
+                .with_label_values(&[
+                    rule.reason.name(),
+                    SANCTION_SOURCE,
+                    rule.sanction.name(),
+                ])
+                .inc();
+
+            info!(
+                to = target,
+                reason = rule.reason.name(),
+                sanction = rule.sanction.name(),
+                "Automatic sanction taken."
+            );
+
+            taken.push(message);
+        }
+
+        Ok(taken)
+    }
+
+    /// Get the rules of the policy tripped by `event`, except those whose
+    /// sanction is still in force against the target.
+    ///
+    /// `pending` are records which have not been saved, such as the one of
+    /// `event`.
+    async fn tripped_rules(
+        &self,
+        event: &Event,
+        pending: &[Record],
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Rule>, Error> {
+        let policy = self.engine.policy();
+        let rules: Vec<_> = policy
+            .rules_for(event.data.r#type, &event.data.reason, &event.source)
+            .collect();
+
+        if rules.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let target = event.data.to.as_str();
+        let mut sanctions = self.storage.sanctions_against(target).await?;
+        let mut reports = match event.data.r#type {
+            Type::Report => {
+                let window = rules
+                    .iter()
+                    .map(|rule| rule.window)
+                    .max()
+                    .unwrap_or_default();
+
+                self.storage
+                    .reports_between(target, now - window, now)
+                    .await?
+            },
+            Type::Sanction => Vec::new(),
+        };
+
+        for record in pending {
+            match record {
+                Record::Report(report) if report.target == target => {
+                    reports.push(report.clone())
+                },
+                Record::Sanction(sanction) if sanction.target == target => {
+                    sanctions.push(sanction.clone())
+                },
+                _ => {},
+            }
+        }
+
+        Ok(rules
+            .into_iter()
+            .filter(|rule| {
+                rule.trips(&reports, &sanctions, now)
+                    && !rule.is_enforced(&sanctions, now)
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+/// What happened to a processed event.
+#[derive(Debug)]
+pub enum Outcome {
+    /// The event had already been processed and was ignored.
+    Duplicate,
+    /// The event was saved and led to `sanctions`.
+    Accepted {
+        /// Automatic sanctions taken against the target.
+        sanctions: Vec<Event<SanctionData>>,
+    },
+}
+
+/// Events of a dry run, as if they had been processed.
+#[derive(Default)]
+#[allow(missing_debug_implementations)]
+pub struct Simulation {
+    /// Simulated events, by `source` and `id`.
+    events: HashSet<(String, String)>,
+    /// Rows which would have been saved.
+    records: Vec<Record>,
+}
+
+/// Row saved for an event.
+enum Record {
+    Report(Report),
+    Sanction(Sanction),
+}
+
+/// Build the row saved for `event`.
+fn record(event: &Event) -> Record {
+    let id = event_id(event);
+    let time = event_time(event);
+    let date = time.date_naive();
+
+    match event.data.r#type {
+        Type::Report => Record::Report(Report {
+            id,
+            date,
+            time: Some(time),
+            source: event.source.clone(),
+            target: event.data.to.clone(),
+            reason: event.data.reason.code(),
+            text_reason: event.data.reason.text(),
+        }),
+        Type::Sanction => Record::Sanction(Sanction {
+            id,
+            date,
+            source: event.source.clone(),
+            target: event.data.to.clone(),
+            reason: event.data.reason.code(),
+            text_reason: event.data.reason.text(),
+            sanction: event
+                .data
+                .sanction
+                .as_ref()
+                .map(|sanction| sanction.code()),
+            expires_at: event.data.expires_at,
+            status: Some(Status::Active.code()),
+        }),
+    }
+}
+
+/// Row of the sanction taken by `rule` against `target`, announced by
+/// `message`.
+fn automatic_sanction(
+    id: Uuid,
+    target: &str,
+    rule: &Rule,
+    message: &Event<SanctionData>,
+    now: DateTime<Utc>,
+) -> Sanction {
+    Sanction {
+        id,
+        date: now.date_naive(),
+        source: SANCTION_SOURCE.to_string(),
+        target: target.to_string(),
+        reason: rule.reason.code(),
+        text_reason: rule.reason.text(),
+        sanction: Some(rule.sanction.code()),
+        expires_at: message.data.expires_at,
+        status: Some(Status::Active.code()),
+    }
+}
+
+/// Identifier used to save the event.
+///
+/// CloudEvents `id` is only unique for a given `source`, so a stable UUID
+/// is derived from both, even if `id` is a UUID. A redelivered event
+/// overwrites the same row.
+fn event_id<T>(event: &Event<T>) -> Uuid {
+    Uuid::new_v5(
+        &Uuid::NAMESPACE_URL,
+        format!("{}#{}", event.source, event.id).as_bytes(),
+    )
+}
+
+/// Identifier of the sanction taken by `rule` because of `event`.
+fn sanction_id(event: &Event, rule: &Rule) -> Uuid {
+    Uuid::new_v5(
+        &Uuid::NAMESPACE_URL,
+        format!("{}#{}#{}", event.source, event.id, rule.name()).as_bytes(),
+    )
+}
+
+/// Time at which the event occurred, or now if `time` is not RFC 3339.
+///
+/// Events slightly ahead of our clock are dated now, so they are found by
+/// windows ending now.
+fn event_time(event: &Event) -> DateTime<Utc> {
+    let now = Utc::now();
+
+    DateTime::parse_from_rfc3339(&event.time)
+        .map_or(now, |time| time.with_timezone(&Utc).min(now))
+}
@@ -0,0 +1,420 @@
+//! Process dead letters or archived events again, see `signaly replay`.
+
+use std::{fmt, time::Instant};
+
+use chrono::{DateTime, Duration, Utc};
+use signaly_db::broker::{
+    Acknowledgement, Delivery, Origin, Publisher, Subscriber,
+};
+use signaly_db::cassandra::{DeadLetter, Manager as ScyllaManager};
+use signaly_db::storage::Storage;
+use signaly_error::{Error, ErrorType, IoError};
+
+use crate::broker::DeadLetterQueue;
+use crate::cli::ReplayArgs;
+use crate::models::{DeadLetterEnvelope, Event, Reason, Type};
+use crate::pipeline::{Outcome, Pipeline, Simulation};
+use crate::validation;
+
+/// Period kept by `dead_letters` table.
+const DEAD_LETTERS_TTL_DAYS: i64 = 30;
+/// Time without dead letter after which a queue is considered read.
+#[cfg(not(test))]
+const IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+#[cfg(test)]
+const IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Events to replay.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// Only replay events of this CloudEvents `type`.
+    pub r#type: Option<String>,
+    /// Only replay events whose `source` starts with this prefix.
+    pub source: Option<String>,
+    /// Only replay events occurred from this time.
+    pub from: Option<DateTime<Utc>>,
+    /// Only replay events occurred until this time.
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl Filter {
+    /// Remove the time range, to select dead letters by reception time
+    /// instead of events by their `time`.
+    ///
+    /// Defaults to the period kept by `dead_letters` table.
+    fn take_range(&mut self) -> (DateTime<Utc>, DateTime<Utc>) {
+        let to = self.to.take().unwrap_or_else(Utc::now);
+        let from = self
+            .from
+            .take()
+            .unwrap_or_else(|| to - Duration::days(DEAD_LETTERS_TTL_DAYS));
+
+        (from, to)
+    }
+
+    /// Check whether `event` is replayed.
+    ///
+    /// Events without RFC 3339 `time` are skipped if a period is set.
+    pub fn matches(&self, event: &Event) -> bool {
+        let in_period = match (self.from, self.to) {
+            (None, None) => true,
+            (from, to) => DateTime::parse_from_rfc3339(&event.time)
+                .map(|time| time.with_timezone(&Utc))
+                .is_ok_and(|time| {
+                    from.is_none_or(|from| time >= from)
+                        && to.is_none_or(|to| time <= to)
+                }),
+        };
+
+        in_period
+            && self
+                .r#type
+                .as_ref()
+                .is_none_or(|r#type| &event.r#type == r#type)
+            && self
+                .source
+                .as_ref()
+                .is_none_or(|prefix| event.source.starts_with(prefix))
+    }
+}
+
+/// Count of replayed events by outcome.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Events saved, or which would be saved.
+    pub accepted: usize,
+    /// Events already processed.
+    pub duplicates: usize,
+    /// Events still invalid.
+    pub invalid: usize,
+    /// Events excluded by the filter.
+    pub filtered: usize,
+    /// Automatic sanctions taken, or which would be taken.
+    pub sanctions: usize,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} accepted, {} duplicates, {} invalid, {} filtered, {} sanctions",
+            self.accepted,
+            self.duplicates,
+            self.invalid,
+            self.filtered,
+            self.sanctions
+        )
+    }
+}
+
+/// Message read from `dead_letters` table or a file.
+struct Entry {
+    /// Position in the input, printed with the outcome.
+    label: String,
+    payload: Vec<u8>,
+    /// Dead letter deleted once the message is processed.
+    letter: Option<DeadLetter>,
+}
+
+/// What happened to a replayed message.
+enum Replayed {
+    /// The event has been processed, or simulated.
+    Processed,
+    /// The event is excluded by the filter.
+    Filtered,
+    /// The message is still invalid, because of the error.
+    Invalid(String),
+}
+
+/// State of a replay, shared by every input.
+struct Replay<'a, S, P> {
+    pipeline: &'a Pipeline<S, P>,
+    filter: Filter,
+    dry_run: bool,
+    /// Events of a dry run, so later events count them.
+    simulation: Simulation,
+    summary: Summary,
+}
+
+impl<'a, S: Storage, P: Publisher> Replay<'a, S, P> {
+    fn new(pipeline: &'a Pipeline<S, P>, args: &ReplayArgs) -> Self {
+        Replay {
+            pipeline,
+            filter: Filter {
+                r#type: args.r#type.clone(),
+                source: args.source.clone(),
+                from: args.from,
+                to: args.to,
+            },
+            dry_run: args.dry_run,
+            simulation: Simulation::default(),
+            summary: Summary::default(),
+        }
+    }
+
+    /// Process or simulate the message at `label`, then print the outcome.
+    async fn message(
+        &mut self,
+        label: &str,
+        payload: &[u8],
+    ) -> Result<Replayed, Error> {
+        let event = match validation::parse(payload) {
+            Ok(event) => event,
+            Err(rejection) => {
+                self.summary.invalid += 1;
+                println!("{}: invalid, {}", label, rejection);
+                return Ok(Replayed::Invalid(rejection.to_string()));
+            },
+        };
+
+        if !self.filter.matches(&event) {
+            self.summary.filtered += 1;
+            return Ok(Replayed::Filtered);
+        }
+
+        let outcome = if self.dry_run {
+            self.pipeline.simulate(&event, &mut self.simulation).await?
+        } else {
+            self.pipeline.process(&event).await?
+        };
+
+        match outcome {
+            Outcome::Duplicate => {
+                self.summary.duplicates += 1;
+                println!(
+                    "{}: duplicate of {} from {}, ignored",
+                    label, event.id, event.source
+                );
+            },
+            Outcome::Accepted { sanctions } => {
+                self.summary.accepted += 1;
+                self.summary.sanctions += sanctions.len();
+                println!(
+                    "{}: {} {} against {} for {}",
+                    label,
+                    if self.dry_run { "would save" } else { "saved" },
+                    match event.data.r#type {
+                        Type::Report => "report",
+                        Type::Sanction => "sanction",
+                    },
+                    event.data.to,
+                    reason(&event.data.reason),
+                );
+
+                for sanction in sanctions {
+                    println!(
+                        "  {} {} against {} for {}",
+                        if self.dry_run { "would take" } else { "took" },
+                        sanction.data.sanction.name(),
+                        sanction.data.to,
+                        sanction.data.reason,
+                    );
+                }
+            },
+        }
+
+        Ok(Replayed::Processed)
+    }
+}
+
+/// Run `signaly replay`.
+///
+/// Messages are read from the NDJSON file given by `args`, or from
+/// `dead_letters` table. The time range selects dead letters by
+/// reception time, and events of a file by their `time`. Dead letters are
+/// deleted once processed; those still invalid are kept.
+pub async fn run<S: Storage, P: Publisher>(
+    pipeline: &Pipeline<S, P>,
+    scylla: &ScyllaManager,
+    args: &ReplayArgs,
+) -> Result<Summary, Error> {
+    let mut replay = Replay::new(pipeline, args);
+
+    let entries = match &args.file {
+        Some(path) => {
+            let content = std::fs::read_to_string(path).map_err(|error| {
+                Error::new(
+                    ErrorType::InuputOutput(IoError::ReadError),
+                    Some(Box::new(error)),
+                    Some(format!("while reading {}", path.display())),
+                )
+            })?;
+
+            content
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(index, line)| Entry {
+                    label: format!("line {}", index + 1),
+                    payload: line.as_bytes().to_vec(),
+                    letter: None,
+                })
+                .collect()
+        },
+        None => {
+            let (from, to) = replay.filter.take_range();
+
+            let mut letters = scylla.dead_letters_between(from, to).await?;
+            // Replay in reception order.
+            letters.reverse();
+
+            letters
+                .into_iter()
+                .map(|letter| Entry {
+                    label: format!("dead letter {}", letter.id),
+                    payload: letter.payload.clone(),
+                    letter: Some(letter),
+                })
+                .collect::<Vec<_>>()
+        },
+    };
+
+    for entry in entries {
+        let replayed = replay.message(&entry.label, &entry.payload).await?;
+
+        if let (Some(letter), Replayed::Processed, false) =
+            (&entry.letter, replayed, args.dry_run)
+        {
+            scylla.delete_dead_letter(letter).await?;
+        }
+    }
+
+    Ok(replay.summary)
+}
+
+/// Run `signaly replay` on a dead letter queue read by `subscriber`.
+///
+/// The time range selects dead letters by rejection time. Dead letters are
+/// acknowledged once processed; those still invalid or excluded by the
+/// filter are sent back to `dead_letters`, which is `None` in dry runs.
+/// Dry runs acknowledge nothing: dead letters go back to their queue when
+/// the subscriber stops, so it must not limit unacknowledged deliveries.
+///
+/// Reading stops at the first dead letter rejected or sent back since the
+/// replay started, or once no dead letter is received for a few seconds.
+pub async fn run_queue<S, P, T>(
+    pipeline: &Pipeline<S, P>,
+    mut subscriber: T,
+    dead_letters: Option<&DeadLetterQueue<P>>,
+    args: &ReplayArgs,
+) -> Result<Summary, Error>
+where
+    S: Storage,
+    P: Publisher,
+    T: Subscriber,
+{
+    let started_at = Utc::now();
+    let mut replay = Replay::new(pipeline, args);
+    let (from, to) = replay.filter.take_range();
+    let mut idle_since = Instant::now();
+
+    'receive: loop {
+        let deliveries = match tokio::time::timeout(
+            IDLE_TIMEOUT.saturating_sub(idle_since.elapsed()),
+            subscriber.receive(),
+        )
+        .await
+        {
+            Ok(deliveries) => deliveries?,
+            Err(_) => break,
+        };
+
+        if deliveries.is_empty() {
+            if idle_since.elapsed() >= IDLE_TIMEOUT {
+                break;
+            }
+            continue;
+        }
+        idle_since = Instant::now();
+
+        for Delivery { origin, payload } in deliveries {
+            let label = label(&origin);
+            // Messages dead lettered by RabbitMQ are not wrapped.
+            let mut envelope =
+                serde_json::from_slice::<DeadLetterEnvelope>(&payload)
+                    .unwrap_or_else(|_| {
+                        DeadLetterEnvelope::new(
+                            &payload,
+                            &origin,
+                            "rejected by the broker".to_string(),
+                            started_at,
+                        )
+                    });
+
+            if envelope.failed_at > started_at
+                || envelope
+                    .replayed_at
+                    .is_some_and(|replayed_at| replayed_at >= started_at)
+            {
+                break 'receive;
+            }
+
+            let replayed =
+                if envelope.failed_at < from || envelope.failed_at > to {
+                    replay.summary.filtered += 1;
+                    Replayed::Filtered
+                } else {
+                    match envelope.payload() {
+                        Ok(payload) => replay.message(&label, &payload).await?,
+                        Err(error) => {
+                            replay.summary.invalid += 1;
+                            println!("{}: invalid, {}", label, error);
+                            Replayed::Invalid(error.to_string())
+                        },
+                    }
+                };
+
+            if args.dry_run {
+                continue;
+            }
+
+            match replayed {
+                Replayed::Processed => {},
+                Replayed::Filtered | Replayed::Invalid(_) => {
+                    let Some(queue) = dead_letters else {
+                        // Left in the queue.
+                        continue;
+                    };
+
+                    if let Replayed::Invalid(error) = replayed {
+                        envelope.error = error;
+                    }
+                    envelope.replayed_at = Some(Utc::now());
+                    queue.send(&envelope).await?;
+                },
+            }
+
+            subscriber
+                .acknowledge(&origin, Acknowledgement::Ack)
+                .await?;
+        }
+    }
+
+    subscriber.close().await?;
+
+    Ok(replay.summary)
+}
+
+/// Position of a dead letter in its queue.
+fn label(origin: &Origin) -> String {
+    match (origin.partition, origin.offset, origin.delivery_tag) {
+        (Some(partition), Some(offset), _) => {
+            format!("dead letter {}/{} at {}", origin.topic, partition, offset)
+        },
+        (_, _, Some(tag)) => {
+            format!("dead letter {} #{}", origin.topic, tag)
+        },
+        (_, Some(offset), _) => {
+            format!("dead letter {} at {}", origin.topic, offset)
+        },
+        _ => format!("dead letter {}", origin.topic),
+    }
+}
+
+/// Name of the reason, with its text for [`Reason::Other`].
+fn reason(reason: &Reason) -> String {
+    match reason.text() {
+        Some(text) => format!("{} ({})", reason.name(), text),
+        None => reason.name().to_string(),
+    }
+}
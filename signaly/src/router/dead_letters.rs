@@ -0,0 +1,87 @@
+//! Handlers of `/dead-letters` routes.
+
+use std::{convert::Infallible, sync::Arc};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use signaly_db::cassandra::{DeadLetter, Manager as ScyllaManager};
+use warp::{
+    http::StatusCode,
+    reply::{self, Response},
+    Reply,
+};
+
+use super::{error_reply, internal_error};
+
+/// Longest period which can be listed at once.
+const MAX_RANGE_DAYS: i64 = 31;
+
+/// Query parameters of `/dead-letters`.
+#[derive(Deserialize)]
+pub struct Range {
+    /// Start of the period, a day before `to` if not set.
+    from: Option<DateTime<Utc>>,
+    /// End of the period, now if not set.
+    to: Option<DateTime<Utc>>,
+}
+
+/// Dead letter sent to the client.
+#[derive(Serialize)]
+struct DeadLetterResponse {
+    id: String,
+    received_at: DateTime<Utc>,
+    /// Payload decoded as UTF-8, invalid sequences being replaced.
+    payload: String,
+    error: String,
+    topic: Option<String>,
+    partition: Option<i32>,
+    offset: Option<i64>,
+    delivery_tag: Option<i64>,
+}
+
+impl From<DeadLetter> for DeadLetterResponse {
+    fn from(letter: DeadLetter) -> Self {
+        DeadLetterResponse {
+            id: letter.id.to_string(),
+            received_at: letter.received_at,
+            payload: String::from_utf8_lossy(&letter.payload).into_owned(),
+            error: letter.error,
+            topic: letter.topic,
+            partition: letter.partition,
+            offset: letter.offset,
+            delivery_tag: letter.delivery_tag,
+        }
+    }
+}
+
+/// List messages which cannot be processed.
+pub async fn list(
+    range: Range,
+    denied: Option<Response>,
+    scylla: Arc<ScyllaManager>,
+) -> Result<Response, Infallible> {
+    if let Some(denied) = denied {
+        return Ok(denied);
+    }
+
+    let to = range.to.unwrap_or_else(Utc::now);
+    let from = range.from.unwrap_or(to - Duration::days(1));
+
+    if from > to || to - from > Duration::days(MAX_RANGE_DAYS) {
+        return Ok(error_reply(
+            StatusCode::BAD_REQUEST,
+            "from must be before to, at most 31 days apart",
+        ));
+    }
+
+    match scylla.dead_letters_between(from, to).await {
+        Ok(letters) => Ok(reply::json(
+            &letters
+                .into_iter()
+                .map(DeadLetterResponse::from)
+                .collect::<Vec<_>>(),
+        )
+        .into_response()),
+        Err(error) => Ok(internal_error(error)),
+    }
+}
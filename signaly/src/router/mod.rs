@@ -1 +1,294 @@
+//! REST API used by moderators to query reports and sanctions.
+//!
+//! Routes:
+//! - `GET /reports?target=<vanity>`: reports made against a target;
+//! - `GET /reports/count?target=<vanity>`: number of reports per reason;
+//! - `GET /reports/<id>`: a single report;
+//! - `GET /sanctions?target=<vanity>`: sanctions taken against a target;
+//! - `POST /sanctions/<id>/revoke`: lift a sanction;
+//! - `POST /sanctions/<id>/appeal`: contest a sanction;
+//! - `GET /dead-letters?from=<time>&to=<time>`: messages which cannot be
+//!   processed.
+//!
+//! Lists accept `page_size` and `paging_state` query parameters. The
+//! `paging_state` returned with a page must be sent back to get the next
+//! one.
+//!
+//! Every route requires the admin token in an `Authorization: Bearer`
+//! header, since reports and dead letters hold reporter data. Routes are
+//! disabled without token.
 
+mod dead_letters;
+mod reports;
+mod sanctions;
+
+use std::{
+    convert::Infallible,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use signaly_db::cassandra::Manager as ScyllaManager;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+use warp::{
+    http::StatusCode,
+    reply::{self, Response},
+    Filter, Rejection, Reply,
+};
+
+use crate::sanction::lifecycle::Lifecycle;
+
+/// Number of rows returned when `page_size` is not set.
+const DEFAULT_PAGE_SIZE: i32 = 50;
+/// Maximum number of rows returned in a single page.
+const MAX_PAGE_SIZE: i32 = 500;
+
+/// Query parameters of paginated lists.
+#[derive(Deserialize)]
+struct Listing {
+    /// Vanity of the user or content.
+    target: String,
+    /// Number of rows per page.
+    page_size: Option<i32>,
+    /// Hex-encoded paging state returned with the previous page.
+    paging_state: Option<String>,
+}
+
+impl Listing {
+    /// Page size bounded to [`MAX_PAGE_SIZE`].
+    fn page_size(&self) -> i32 {
+        self.page_size
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    /// Decoded paging state.
+    fn paging_state(&self) -> Result<Option<Vec<u8>>, hex::FromHexError> {
+        self.paging_state.as_deref().map(hex::decode).transpose()
+    }
+}
+
+/// Query parameters of aggregations.
+#[derive(Deserialize)]
+struct Target {
+    /// Vanity of the user or content.
+    target: String,
+}
+
+/// Page of results sent to the client.
+#[derive(Serialize)]
+struct PageResponse<T> {
+    data: Vec<T>,
+    /// Hex-encoded state to get the next page, `null` on the last page.
+    paging_state: Option<String>,
+}
+
+impl<T> PageResponse<T> {
+    fn new(data: Vec<T>, paging_state: Option<Vec<u8>>) -> Self {
+        PageResponse {
+            data,
+            paging_state: paging_state.map(hex::encode),
+        }
+    }
+}
+
+/// Create a JSON reply containing an error message.
+fn error_reply(status: StatusCode, message: &str) -> Response {
+    reply::with_status(
+        reply::json(&serde_json::json!({ "error": message })),
+        status,
+    )
+    .into_response()
+}
+
+/// Create the reply sent when `paging_state` cannot be decoded.
+fn invalid_paging_state() -> Response {
+    error_reply(StatusCode::BAD_REQUEST, "invalid paging_state")
+}
+
+/// Log a database error and create the reply sent to the client.
+///
+/// Errors can also come from the broker when a message cannot be sent.
+fn internal_error(error: signaly_error::Error) -> Response {
+    error!(
+        error = error.to_string(),
+        context = error.context.as_deref(),
+        "Cannot handle API request."
+    );
+
+    error_reply(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+}
+
+fn with_scylla(
+    scylla: Arc<ScyllaManager>,
+) -> impl Filter<Extract = (Arc<ScyllaManager>,), Error = Infallible> + Clone {
+    warp::any().map(move || Arc::clone(&scylla))
+}
+
+/// Check the admin token of the request against `token`.
+///
+/// Extracts the reply sent instead of handling the request, if any.
+fn with_admin(
+    token: Option<Arc<str>>,
+) -> impl Filter<Extract = (Option<Response>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization").map(
+        move |header: Option<String>| {
+            let Some(token) = &token else {
+                return Some(error_reply(
+                    StatusCode::FORBIDDEN,
+                    "admin routes are disabled",
+                ));
+            };
+
+            let given = header
+                .as_deref()
+                .and_then(|header| header.strip_prefix("Bearer "));
+            match given {
+                Some(given) if constant_time_eq(given, token) => None,
+                _ => Some(error_reply(
+                    StatusCode::UNAUTHORIZED,
+                    "invalid admin token",
+                )),
+            }
+        },
+    )
+}
+
+/// Compare secrets without leaking where they differ through timing.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn with_lifecycle(
+    lifecycle: Arc<Lifecycle>,
+) -> impl Filter<Extract = (Arc<Lifecycle>,), Error = Infallible> + Clone {
+    warp::any().map(move || Arc::clone(&lifecycle))
+}
+
+/// Every route of the API.
+pub fn routes(
+    scylla: Arc<ScyllaManager>,
+    lifecycle: Arc<Lifecycle>,
+    admin_token: Option<Arc<str>>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let list_reports = warp::path!("reports")
+        .and(warp::get())
+        .and(warp::query::<Listing>())
+        .and(with_admin(admin_token.clone()))
+        .and(with_scylla(Arc::clone(&scylla)))
+        .and_then(reports::list);
+    let count_reports = warp::path!("reports" / "count")
+        .and(warp::get())
+        .and(warp::query::<Target>())
+        .and(with_admin(admin_token.clone()))
+        .and(with_scylla(Arc::clone(&scylla)))
+        .and_then(reports::count);
+    let get_report = warp::path!("reports" / Uuid)
+        .and(warp::get())
+        .and(with_admin(admin_token.clone()))
+        .and(with_scylla(Arc::clone(&scylla)))
+        .and_then(reports::get);
+    let list_sanctions = warp::path!("sanctions")
+        .and(warp::get())
+        .and(warp::query::<Listing>())
+        .and(with_admin(admin_token.clone()))
+        .and(with_scylla(Arc::clone(&scylla)))
+        .and_then(sanctions::list);
+    let revoke_sanction = warp::path!("sanctions" / Uuid / "revoke")
+        .and(warp::post())
+        .and(with_admin(admin_token.clone()))
+        .and(with_lifecycle(Arc::clone(&lifecycle)))
+        .and_then(sanctions::revoke);
+    let appeal_sanction = warp::path!("sanctions" / Uuid / "appeal")
+        .and(warp::post())
+        .and(with_admin(admin_token.clone()))
+        .and(with_lifecycle(lifecycle))
+        .and_then(sanctions::appeal);
+    let list_dead_letters = warp::path!("dead-letters")
+        .and(warp::get())
+        .and(warp::query::<dead_letters::Range>())
+        .and(with_admin(admin_token.clone()))
+        .and(with_scylla(scylla))
+        .and_then(dead_letters::list);
+
+    list_reports
+        .or(count_reports)
+        .or(get_report)
+        .or(list_sanctions)
+        .or(revoke_sanction)
+        .or(appeal_sanction)
+        .or(list_dead_letters)
+}
+
+/// Create the HTTP server handling the API on `port`.
+///
+/// Routes are only handled for requests with `admin_token`.
+pub async fn serve(
+    scylla: Arc<ScyllaManager>,
+    lifecycle: Arc<Lifecycle>,
+    port: u16,
+    admin_token: Option<String>,
+) {
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
+    info!(
+        "Server is listening to {} awaiting requests for the API.",
+        addr
+    );
+
+    if admin_token.is_none() {
+        warn!("No admin token (api.admin_token), every API route is disabled.");
+    }
+
+    warp::serve(routes(scylla, lifecycle, admin_token.map(Arc::from)))
+        .run(addr)
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn status(token: Option<&str>, header: Option<&str>) -> StatusCode {
+        let filter =
+            with_admin(token.map(Arc::from)).map(|denied: Option<Response>| {
+                denied.unwrap_or_else(|| StatusCode::OK.into_response())
+            });
+
+        let mut request = warp::test::request();
+        if let Some(header) = header {
+            request = request.header("authorization", header);
+        }
+        request.reply(&filter).await.status()
+    }
+
+    #[tokio::test]
+    async fn admin_routes_require_the_token() {
+        let token = Some("secret");
+
+        assert_eq!(status(token, Some("Bearer secret")).await, StatusCode::OK);
+        assert_eq!(
+            status(token, Some("Bearer other")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(token, Some("secret")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(status(token, None).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn admin_routes_are_disabled_without_token() {
+        assert_eq!(status(None, None).await, StatusCode::FORBIDDEN);
+        assert_eq!(
+            status(None, Some("Bearer secret")).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+}
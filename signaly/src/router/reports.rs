@@ -0,0 +1,128 @@
+//! Handlers of `/reports` routes.
+
+use std::{collections::BTreeMap, convert::Infallible, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use signaly_db::cassandra::{Manager as ScyllaManager, Report};
+use uuid::Uuid;
+use warp::{
+    http::StatusCode,
+    reply::{self, Response},
+    Reply,
+};
+
+use super::{
+    error_reply, internal_error, invalid_paging_state, Listing, PageResponse,
+    Target,
+};
+use crate::models::Reason;
+
+/// Report sent to the client.
+#[derive(Serialize)]
+struct ReportResponse {
+    id: String,
+    date: String,
+    time: Option<DateTime<Utc>>,
+    source: String,
+    target: String,
+    reason: &'static str,
+    text_reason: Option<String>,
+}
+
+impl From<Report> for ReportResponse {
+    fn from(report: Report) -> Self {
+        ReportResponse {
+            id: report.id.to_string(),
+            date: report.date.to_string(),
+            time: report.time,
+            source: report.source,
+            target: report.target,
+            reason: Reason::name_of(report.reason),
+            text_reason: report.text_reason,
+        }
+    }
+}
+
+/// Number of reports made against a target.
+#[derive(Serialize)]
+struct CountResponse {
+    target: String,
+    total: usize,
+    reasons: BTreeMap<&'static str, usize>,
+}
+
+/// List reports made against a target.
+pub async fn list(
+    listing: Listing,
+    denied: Option<Response>,
+    scylla: Arc<ScyllaManager>,
+) -> Result<Response, Infallible> {
+    if let Some(denied) = denied {
+        return Ok(denied);
+    }
+
+    let paging_state = match listing.paging_state() {
+        Ok(paging_state) => paging_state,
+        Err(_) => return Ok(invalid_paging_state()),
+    };
+
+    match scylla
+        .reports_page(&listing.target, listing.page_size(), paging_state)
+        .await
+    {
+        Ok(page) => Ok(reply::json(&PageResponse::new(
+            page.rows.into_iter().map(ReportResponse::from).collect(),
+            page.paging_state,
+        ))
+        .into_response()),
+        Err(error) => Ok(internal_error(error)),
+    }
+}
+
+/// Count reports made against a target per reason.
+pub async fn count(
+    query: Target,
+    denied: Option<Response>,
+    scylla: Arc<ScyllaManager>,
+) -> Result<Response, Infallible> {
+    if let Some(denied) = denied {
+        return Ok(denied);
+    }
+
+    match scylla.count_reports(&query.target).await {
+        Ok(counts) => {
+            let mut reasons = BTreeMap::new();
+            for (reason, count) in counts {
+                *reasons.entry(Reason::name_of(reason)).or_insert(0) += count;
+            }
+
+            Ok(reply::json(&CountResponse {
+                target: query.target,
+                total: reasons.values().sum(),
+                reasons,
+            })
+            .into_response())
+        },
+        Err(error) => Ok(internal_error(error)),
+    }
+}
+
+/// Get a single report.
+pub async fn get(
+    id: Uuid,
+    denied: Option<Response>,
+    scylla: Arc<ScyllaManager>,
+) -> Result<Response, Infallible> {
+    if let Some(denied) = denied {
+        return Ok(denied);
+    }
+
+    match scylla.report(id).await {
+        Ok(Some(report)) => {
+            Ok(reply::json(&ReportResponse::from(report)).into_response())
+        },
+        Ok(None) => Ok(error_reply(StatusCode::NOT_FOUND, "report not found")),
+        Err(error) => Ok(internal_error(error)),
+    }
+}
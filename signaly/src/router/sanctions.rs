@@ -0,0 +1,127 @@
+//! Handlers of `/sanctions` routes.
+
+use std::{convert::Infallible, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use signaly_db::cassandra::{
+    Manager as ScyllaManager, Sanction as SanctionRecord,
+};
+use signaly_error::Error;
+use uuid::Uuid;
+use warp::{
+    http::StatusCode,
+    reply::{self, Response},
+    Reply,
+};
+
+use super::{
+    error_reply, internal_error, invalid_paging_state, Listing, PageResponse,
+};
+use crate::models::{Reason, Sanction, Status};
+use crate::sanction::lifecycle::{self, Lifecycle, Outcome};
+
+/// Sanction sent to the client.
+#[derive(Serialize)]
+struct SanctionResponse {
+    id: String,
+    date: String,
+    source: String,
+    target: String,
+    reason: &'static str,
+    text_reason: Option<String>,
+    sanction: Option<&'static str>,
+    expires_at: Option<DateTime<Utc>>,
+    status: Status,
+}
+
+impl From<SanctionRecord> for SanctionResponse {
+    fn from(sanction: SanctionRecord) -> Self {
+        let status = lifecycle::status(&sanction, Utc::now());
+
+        SanctionResponse {
+            id: sanction.id.to_string(),
+            date: sanction.date.to_string(),
+            source: sanction.source,
+            target: sanction.target,
+            reason: Reason::name_of(sanction.reason),
+            text_reason: sanction.text_reason,
+            sanction: sanction
+                .sanction
+                .and_then(Sanction::from_code)
+                .map(|sanction| sanction.name()),
+            expires_at: sanction.expires_at,
+            status,
+        }
+    }
+}
+
+/// List sanctions taken against a target.
+pub async fn list(
+    listing: Listing,
+    denied: Option<Response>,
+    scylla: Arc<ScyllaManager>,
+) -> Result<Response, Infallible> {
+    if let Some(denied) = denied {
+        return Ok(denied);
+    }
+
+    let paging_state = match listing.paging_state() {
+        Ok(paging_state) => paging_state,
+        Err(_) => return Ok(invalid_paging_state()),
+    };
+
+    match scylla
+        .sanctions_page(&listing.target, listing.page_size(), paging_state)
+        .await
+    {
+        Ok(page) => Ok(reply::json(&PageResponse::new(
+            page.rows.into_iter().map(SanctionResponse::from).collect(),
+            page.paging_state,
+        ))
+        .into_response()),
+        Err(error) => Ok(internal_error(error)),
+    }
+}
+
+/// Lift a sanction.
+pub async fn revoke(
+    id: Uuid,
+    denied: Option<Response>,
+    lifecycle: Arc<Lifecycle>,
+) -> Result<Response, Infallible> {
+    if let Some(denied) = denied {
+        return Ok(denied);
+    }
+
+    Ok(outcome_reply(lifecycle.revoke(id).await))
+}
+
+/// Contest a sanction.
+pub async fn appeal(
+    id: Uuid,
+    denied: Option<Response>,
+    lifecycle: Arc<Lifecycle>,
+) -> Result<Response, Infallible> {
+    if let Some(denied) = denied {
+        return Ok(denied);
+    }
+
+    Ok(outcome_reply(lifecycle.appeal(id).await))
+}
+
+fn outcome_reply(outcome: Result<Outcome, Error>) -> Response {
+    match outcome {
+        Ok(Outcome::Changed(sanction)) => {
+            reply::json(&SanctionResponse::from(sanction)).into_response()
+        },
+        Ok(Outcome::NotFound) => {
+            error_reply(StatusCode::NOT_FOUND, "sanction not found")
+        },
+        Ok(Outcome::Conflict(status)) => error_reply(
+            StatusCode::CONFLICT,
+            &format!("sanction is {}", status.name()),
+        ),
+        Err(error) => internal_error(error),
+    }
+}
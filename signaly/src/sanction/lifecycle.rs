@@ -0,0 +1,386 @@
+//! Expiry, revocation and appeal of sanctions.
+//!
+//! Every change of status is announced with a
+//! `com.gravitalia.sanction.<status>` message. Announcements are saved
+//! before the change, and sent again until they reach every broker.
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use signaly_db::cassandra::{
+    Manager as ScyllaManager, PendingAnnouncement, Sanction as SanctionRecord,
+};
+use signaly_error::{Error, ErrorType};
+use tokio::task;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use super::SANCTION_TYPE;
+use crate::broker::{deliver, Message, Publisher};
+use crate::models::{Event, Reason, Sanction, Status, StatusData};
+
+/// CloudEvents source of status changes.
+pub const LIFECYCLE_SOURCE: &str = "/sanction/lifecycle";
+/// How often sanctions are checked for expiry.
+const EXPIRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+/// How many days of ends and announcements are read, to catch up after
+/// Signaly was stopped.
+const EXPIRY_LOOKBACK_DAYS: i64 = 30;
+
+/// Result of a requested change of status.
+#[derive(Debug)]
+pub enum Outcome {
+    /// The status has been changed.
+    Changed(SanctionRecord),
+    /// No sanction has this identifier.
+    NotFound,
+    /// The sanction cannot move from its current status.
+    Conflict(Status),
+}
+
+/// Change the status of sanctions.
+#[allow(missing_debug_implementations)]
+pub struct Lifecycle {
+    scylla: Arc<ScyllaManager>,
+    publishers: Arc<[Publisher]>,
+    topic: String,
+}
+
+impl Lifecycle {
+    /// Create a new [`Lifecycle`] announcing changes on `topic`.
+    pub fn new(
+        scylla: Arc<ScyllaManager>,
+        publishers: Arc<[Publisher]>,
+        topic: String,
+    ) -> Self {
+        Lifecycle {
+            scylla,
+            publishers,
+            topic,
+        }
+    }
+
+    /// Lift an active or appealed sanction.
+    pub async fn revoke(&self, id: Uuid) -> Result<Outcome, Error> {
+        self.change(id, &[Status::Active, Status::Appealed], Status::Revoked)
+            .await
+    }
+
+    /// Contest an active sanction.
+    pub async fn appeal(&self, id: Uuid) -> Result<Outcome, Error> {
+        self.change(id, &[Status::Active], Status::Appealed).await
+    }
+
+    /// Mark active and appealed sanctions whose end has passed as expired.
+    ///
+    /// Returns the number of expired sanctions.
+    pub async fn expire(&self, now: DateTime<Utc>) -> Result<usize, Error> {
+        let mut expired = 0;
+
+        for end in self
+            .scylla
+            .sanctions_ending(now - Duration::days(EXPIRY_LOOKBACK_DAYS), now)
+            .await?
+        {
+            if let Some(mut sanction) = self.scylla.sanction(end.id).await? {
+                let current = Status::from_code(sanction.status)
+                    .unwrap_or(Status::Active);
+
+                // Ends of revoked or extended sanctions are only deleted.
+                if sanction.expires_at == Some(end.expires_at)
+                    && matches!(current, Status::Active | Status::Appealed)
+                {
+                    if !self.apply(&mut sanction, Status::Expired).await? {
+                        // Changed in the meantime, checked again next time.
+                        continue;
+                    }
+
+                    expired += 1;
+                }
+            }
+
+            self.scylla.delete_sanction_end(&end).await?;
+        }
+
+        Ok(expired)
+    }
+
+    async fn change(
+        &self,
+        id: Uuid,
+        from: &[Status],
+        to: Status,
+    ) -> Result<Outcome, Error> {
+        let Some(mut sanction) = self.scylla.sanction(id).await? else {
+            return Ok(Outcome::NotFound);
+        };
+
+        let current = status(&sanction, Utc::now());
+        if !from.contains(&current) {
+            return Ok(Outcome::Conflict(current));
+        }
+
+        if !self.apply(&mut sanction, to).await? {
+            // Changed by someone else in the meantime.
+            let current = match self.scylla.sanction(id).await? {
+                Some(sanction) => status(&sanction, Utc::now()),
+                None => return Ok(Outcome::NotFound),
+            };
+            return Ok(Outcome::Conflict(current));
+        }
+
+        info!(
+            id = id.to_string(),
+            status = to.name(),
+            "Sanction status changed."
+        );
+
+        Ok(Outcome::Changed(sanction))
+    }
+
+    /// Send again announcements which failed.
+    ///
+    /// Returns the number of sent announcements.
+    pub async fn retry_announcements(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<usize, Error> {
+        let mut sent = 0;
+
+        // Recent announcements may still be sent by their change.
+        for announcement in self
+            .scylla
+            .pending_announcements(
+                now - Duration::days(EXPIRY_LOOKBACK_DAYS),
+                now - Duration::from_std(EXPIRY_INTERVAL).unwrap_or_default(),
+            )
+            .await?
+        {
+            let sanction =
+                self.scylla.sanction(announcement.sanction_id).await?;
+            match (sanction, Status::from_code(Some(announcement.status))) {
+                // The change has been applied.
+                (Some(sanction), Some(status))
+                    if sanction.status == Some(announcement.status) =>
+                {
+                    if self.send(&sanction, status, &announcement).await {
+                        sent += 1;
+                    }
+                },
+                // The change has failed, or has been overridden.
+                _ => self.scylla.delete_announcement(&announcement).await?,
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// Change the status of `sanction` to `status` and announce it.
+    ///
+    /// Returns whether the status has been changed, only if it is unchanged
+    /// in the database.
+    async fn apply(
+        &self,
+        sanction: &mut SanctionRecord,
+        status: Status,
+    ) -> Result<bool, Error> {
+        let announcement = PendingAnnouncement::new(sanction.id, status.code());
+        self.scylla.insert_announcement(&announcement).await?;
+
+        if !self
+            .scylla
+            .update_sanction_status(sanction.id, sanction.status, status.code())
+            .await?
+        {
+            self.scylla.delete_announcement(&announcement).await?;
+            return Ok(false);
+        }
+
+        sanction.status = Some(status.code());
+        self.send(sanction, status, &announcement).await;
+
+        Ok(true)
+    }
+
+    /// Announce the new status of `sanction`, then delete its pending
+    /// `announcement`.
+    ///
+    /// Returns whether the announcement has been sent. Failures are sent
+    /// again by [`Lifecycle::retry_announcements`].
+    async fn send(
+        &self,
+        sanction: &SanctionRecord,
+        status: Status,
+        announcement: &PendingAnnouncement,
+    ) -> bool {
+        let result = match self.announce(sanction, status).await {
+            Ok(()) => self.scylla.delete_announcement(announcement).await,
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = result {
+            warn!(
+                id = sanction.id.to_string(),
+                status = status.name(),
+                error = err.to_string(),
+                "Status change could not be announced, it will be sent again."
+            );
+            return false;
+        }
+
+        true
+    }
+
+    /// Send the message announcing the new status of `sanction`.
+    ///
+    /// The identifier of the message is the same for every attempt.
+    async fn announce(
+        &self,
+        sanction: &SanctionRecord,
+        status: Status,
+    ) -> Result<(), Error> {
+        let id = Uuid::new_v5(
+            &Uuid::NAMESPACE_URL,
+            format!("{}#{}", sanction.id, status.name()).as_bytes(),
+        );
+        let event = Event {
+            specversion: "1.0".to_string(),
+            r#type: format!("{}.{}", SANCTION_TYPE, status.name()),
+            source: LIFECYCLE_SOURCE.to_string(),
+            id: id.to_string(),
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            datacontenttype: "application/json".to_string(),
+            data: StatusData {
+                id: sanction.id.to_string(),
+                to: sanction.target.clone(),
+                reason: Reason::name_of(sanction.reason).to_string(),
+                sanction: sanction.sanction.and_then(Sanction::from_code),
+                status,
+            },
+        };
+
+        let content = serde_json::to_string(&event).map_err(|error| {
+            Error::new(
+                ErrorType::Unspecified,
+                Some(Box::new(error)),
+                Some("while serializing a sanction status".to_string()),
+            )
+        })?;
+
+        deliver(
+            &*self.scylla,
+            &self.publishers,
+            id,
+            &self.topic,
+            Message {
+                key: Some(&sanction.target),
+                routing_key: format!(
+                    "sanction.{}.{}",
+                    event.data.sanction.map_or("unspecified", |sanction| {
+                        sanction.routing_name()
+                    }),
+                    status.name()
+                ),
+                content,
+            },
+        )
+        .await
+    }
+}
+
+/// Current status of a sanction, considering its end.
+pub fn status(sanction: &SanctionRecord, now: DateTime<Utc>) -> Status {
+    match Status::from_code(sanction.status) {
+        Some(Status::Active | Status::Appealed)
+            if sanction.expires_at.is_some_and(|end| end <= now) =>
+        {
+            Status::Expired
+        },
+        Some(status) => status,
+        None => Status::Active,
+    }
+}
+
+/// Periodically expire sanctions whose end has passed, and send failed
+/// announcements again.
+pub fn watch_expiry(lifecycle: Arc<Lifecycle>) {
+    task::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            match lifecycle.retry_announcements(Utc::now()).await {
+                Ok(0) => {},
+                Ok(sent) => info!(sent, "Status changes announced again."),
+                Err(err) => error!(
+                    error = err.to_string(),
+                    "Cannot announce status changes again."
+                ),
+            }
+
+            match lifecycle.expire(Utc::now()).await {
+                Ok(0) => {},
+                Ok(expired) => info!(expired, "Sanctions expired."),
+                Err(err) => {
+                    error!(error = err.to_string(), "Cannot expire sanctions.")
+                },
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sanction::SANCTION_SOURCE;
+
+    fn sanction(
+        status: Status,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> SanctionRecord {
+        SanctionRecord {
+            id: Uuid::nil(),
+            date: Utc::now().date_naive(),
+            source: SANCTION_SOURCE.to_string(),
+            target: "target".to_string(),
+            reason: 0,
+            text_reason: None,
+            sanction: None,
+            expires_at,
+            status: Some(status.code()),
+        }
+    }
+
+    #[test]
+    fn sanctions_past_their_end_are_expired() {
+        let now = Utc::now();
+        let ended = Some(now - Duration::minutes(1));
+        let ending = Some(now + Duration::minutes(1));
+
+        assert_eq!(
+            status(&sanction(Status::Active, ended), now),
+            Status::Expired
+        );
+        assert_eq!(
+            status(&sanction(Status::Appealed, ended), now),
+            Status::Expired
+        );
+        assert_eq!(
+            status(&sanction(Status::Revoked, ended), now),
+            Status::Revoked
+        );
+        assert_eq!(
+            status(&sanction(Status::Active, ending), now),
+            Status::Active
+        );
+        assert_eq!(
+            status(&sanction(Status::Appealed, ending), now),
+            Status::Appealed
+        );
+        assert_eq!(
+            status(&sanction(Status::Active, None), now),
+            Status::Active
+        );
+    }
+}
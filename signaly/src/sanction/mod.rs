@@ -0,0 +1,189 @@
+//! Automatic sanctions taken when a target receives too many reports or
+//! sanctions.
+//!
+//! Rules are loaded from a policy file, see [`policy`].
+
+pub mod lifecycle;
+pub mod policy;
+
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use signaly_db::cassandra::{Report, Sanction as SanctionRecord};
+use uuid::Uuid;
+
+use crate::models::{Event, Reason, Sanction, SanctionData, Status, Type};
+pub use policy::Policy;
+
+/// CloudEvents type of produced sanctions.
+pub const SANCTION_TYPE: &str = "com.gravitalia.sanction";
+/// CloudEvents source of sanctions taken by the [`Engine`].
+pub const SANCTION_SOURCE: &str = "/sanction/toomanyreports";
+
+/// Sanction taken once a target reaches a number of events.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    /// Reason of the counted events.
+    pub reason: Reason,
+    /// Type of the counted events.
+    pub r#type: Type,
+    /// Only count events whose source starts with this prefix.
+    pub source: Option<String>,
+    /// Number of events triggering the sanction.
+    pub threshold: usize,
+    /// Period during which events are counted.
+    pub window: Duration,
+    /// Sanction taken against the target.
+    pub sanction: Sanction,
+    /// Length of the sanction, permanent if not set.
+    pub duration: Option<Duration>,
+}
+
+impl Rule {
+    /// Check whether an event of `type` for `reason` sent by `source` is
+    /// counted by the rule.
+    pub fn applies_to(
+        &self,
+        r#type: Type,
+        reason: &Reason,
+        source: &str,
+    ) -> bool {
+        self.r#type == r#type
+            && self.reason.code() == reason.code()
+            && self.accepts(source)
+    }
+
+    /// Stable description of the rule, such as
+    /// `Report:Nudity:5/86400s:Removal`, identifying the sanctions it
+    /// takes.
+    pub fn name(&self) -> String {
+        format!(
+            "{:?}:{}{}:{}/{}s:{}",
+            self.r#type,
+            self.reason.name(),
+            self.source
+                .as_ref()
+                .map_or(String::new(), |source| format!("@{}", source)),
+            self.threshold,
+            self.window.num_seconds(),
+            self.sanction.name()
+        )
+    }
+
+    /// Check whether the events made against a target trip the rule.
+    ///
+    /// Sanctions and reports saved without time are dated to the day, so
+    /// the window is rounded up to whole days for them. The rule trips as
+    /// long as the count reaches the threshold; see [`Rule::is_enforced`]
+    /// to not sanction the target again.
+    pub fn trips(
+        &self,
+        reports: &[Report],
+        sanctions: &[SanctionRecord],
+        now: DateTime<Utc>,
+    ) -> bool {
+        let since = now - self.window;
+
+        let count = match self.r#type {
+            Type::Report => reports
+                .iter()
+                .filter(|report| {
+                    report.reason == self.reason.code()
+                        && report
+                            .time
+                            .map_or(report.date >= since.date_naive(), |time| {
+                                time >= since
+                            })
+                        && self.accepts(&report.source)
+                })
+                .count(),
+            Type::Sanction => sanctions
+                .iter()
+                .filter(|sanction| {
+                    sanction.reason == self.reason.code()
+                        && Status::from_code(sanction.status)
+                            != Some(Status::Revoked)
+                        && sanction.date >= since.date_naive()
+                        && self.accepts(&sanction.source)
+                })
+                .count(),
+        };
+
+        count >= self.threshold
+    }
+
+    /// Check whether a sanction taken by the rule is still in force
+    /// against the target of `sanctions`.
+    pub fn is_enforced(
+        &self,
+        sanctions: &[SanctionRecord],
+        now: DateTime<Utc>,
+    ) -> bool {
+        sanctions.iter().any(|sanction| {
+            sanction.source == SANCTION_SOURCE
+                && sanction.reason == self.reason.code()
+                && sanction.sanction == Some(self.sanction.code())
+                && matches!(
+                    lifecycle::status(sanction, now),
+                    Status::Active | Status::Appealed
+                )
+        })
+    }
+
+    fn accepts(&self, source: &str) -> bool {
+        self.source
+            .as_ref()
+            .is_none_or(|prefix| source.starts_with(prefix))
+    }
+}
+
+/// Decide which sanctions are taken against a target.
+///
+/// The policy can be replaced while Signaly is running.
+#[derive(Debug, Default)]
+pub struct Engine {
+    policy: RwLock<Arc<Policy>>,
+}
+
+impl Engine {
+    /// Create a new [`Engine`] applying `policy`.
+    pub fn new(policy: Policy) -> Self {
+        Engine {
+            policy: RwLock::new(Arc::new(policy)),
+        }
+    }
+
+    /// Policy currently applied.
+    pub fn policy(&self) -> Arc<Policy> {
+        Arc::clone(&self.policy.read().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    /// Apply a new policy to the following events.
+    pub fn replace(&self, policy: Policy) {
+        *self.policy.write().unwrap_or_else(|err| err.into_inner()) =
+            Arc::new(policy);
+    }
+}
+
+/// Create the message announcing the sanction `id` against `target`.
+pub fn sanction_event(
+    id: Uuid,
+    target: &str,
+    rule: &Rule,
+    now: DateTime<Utc>,
+) -> Event<SanctionData> {
+    Event {
+        specversion: "1.0".to_string(),
+        r#type: SANCTION_TYPE.to_string(),
+        source: SANCTION_SOURCE.to_string(),
+        id: id.to_string(),
+        time: now.to_rfc3339_opts(SecondsFormat::Secs, true),
+        datacontenttype: "application/json".to_string(),
+        data: SanctionData {
+            to: target.to_string(),
+            reason: rule.reason.name().to_string(),
+            sanction: rule.sanction,
+            expires_at: rule.duration.map(|duration| now + duration),
+        },
+    }
+}
@@ -0,0 +1,315 @@
+//! Sanction policy loaded from a TOML file.
+//!
+//! # Example
+//! ```toml
+//! # Remove a content reported 5 times for nudity within 24 hours.
+//! [[rules]]
+//! reason = "Nudity"
+//! threshold = 5
+//! window = "24h"
+//! sanction = "Removal"
+//!
+//! # Suspend for 7 days a user whose contents were removed 3 times by
+//! # Gravitalia within 30 days.
+//! [[rules]]
+//! reason = "Hate"
+//! type = "Sanction"
+//! source = "https://www.gravitalia.com/"
+//! threshold = 3
+//! window = "30d"
+//! sanction = "Suspension"
+//! duration = "7d"
+//! ```
+//!
+//! `type` defaults to `Report`, `source` is an optional prefix and sanctions
+//! without `duration` are permanent. Windows and durations accept `s`, `m`,
+//! `h` and `d` units.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration as StdDuration, SystemTime},
+};
+
+use chrono::Duration;
+use serde::Deserialize;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    task,
+};
+use tracing::{error, info};
+
+use super::{Engine, Rule};
+use crate::models::{Reason, Type};
+
+/// How often the policy file is checked for changes.
+const WATCH_INTERVAL: StdDuration = StdDuration::from_secs(5);
+
+/// Set of rules applied by the [`Engine`].
+#[derive(Debug, Default)]
+pub struct Policy {
+    rules: Vec<Rule>,
+}
+
+impl Policy {
+    /// Read and validate a policy file.
+    pub fn from_file(path: &Path) -> Result<Self, PolicyError> {
+        let content = std::fs::read_to_string(path).map_err(|error| {
+            PolicyError(vec![format!(
+                "cannot read {}: {}",
+                path.display(),
+                error
+            )])
+        })?;
+
+        content.parse()
+    }
+
+    /// Rules counting an event of `type` for `reason` sent by `source`.
+    pub fn rules_for<'a>(
+        &'a self,
+        r#type: Type,
+        reason: &'a Reason,
+        source: &'a str,
+    ) -> impl Iterator<Item = &'a Rule> {
+        self.rules
+            .iter()
+            .filter(move |rule| rule.applies_to(r#type, reason, source))
+    }
+}
+
+impl std::str::FromStr for Policy {
+    type Err = PolicyError;
+
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        let file: PolicyFile = toml::from_str(content)
+            .map_err(|error| PolicyError(vec![error.to_string()]))?;
+
+        let mut rules = Vec::with_capacity(file.rules.len());
+        let mut errors = Vec::new();
+
+        for (index, definition) in file.rules.into_iter().enumerate() {
+            match definition.validate() {
+                Ok(rule) => rules.push(rule),
+                Err(rule_errors) => errors.extend(
+                    rule_errors
+                        .into_iter()
+                        .map(|error| format!("rule #{}: {}", index + 1, error)),
+                ),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(Policy { rules })
+        } else {
+            Err(PolicyError(errors))
+        }
+    }
+}
+
+/// Errors found in a policy file.
+#[derive(Debug)]
+pub struct PolicyError(pub Vec<String>);
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid sanction policy: {}", self.0.join("; "))
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PolicyFile {
+    #[serde(default)]
+    rules: Vec<RuleDefinition>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleDefinition {
+    reason: String,
+    #[serde(default)]
+    r#type: Type,
+    source: Option<String>,
+    threshold: usize,
+    window: String,
+    sanction: String,
+    duration: Option<String>,
+}
+
+impl RuleDefinition {
+    fn validate(self) -> Result<Rule, Vec<String>> {
+        let reason = self.reason.parse::<Reason>();
+        let sanction = self.sanction.parse();
+        let window = parse_window(&self.window);
+        let duration = self.duration.as_deref().map(parse_window).transpose();
+
+        let mut errors = Vec::new();
+        if self.threshold == 0 {
+            errors.push("threshold must be a positive integer".to_string());
+        }
+        if self.source.as_deref() == Some("") {
+            errors.push("source must not be empty".to_string());
+        }
+
+        match (reason, window, sanction, duration) {
+            (Ok(reason), Ok(window), Ok(sanction), Ok(duration))
+                if errors.is_empty() =>
+            {
+                Ok(Rule {
+                    reason,
+                    r#type: self.r#type,
+                    source: self.source,
+                    threshold: self.threshold,
+                    window,
+                    sanction,
+                    duration,
+                })
+            },
+            (reason, window, sanction, duration) => {
+                errors.extend(reason.err());
+                errors.extend(window.err());
+                errors.extend(sanction.err());
+                errors.extend(duration.err());
+                Err(errors)
+            },
+        }
+    }
+}
+
+/// Parse a window or duration such as `30m` or `24h`.
+fn parse_window(window: &str) -> Result<Duration, String> {
+    let Some(unit) = window.chars().last() else {
+        return Err("window must not be empty".to_string());
+    };
+    let value = &window[..window.len() - unit.len_utf8()];
+
+    let to_duration: fn(i64) -> Option<Duration> = match unit {
+        's' => Duration::try_seconds,
+        'm' => Duration::try_minutes,
+        'h' => Duration::try_hours,
+        'd' => Duration::try_days,
+        _ => return Err(format!("unknown unit in window {:?}", window)),
+    };
+
+    value
+        .parse::<i64>()
+        .ok()
+        .filter(|value| *value > 0)
+        .and_then(to_duration)
+        .ok_or_else(|| format!("invalid window {:?}", window))
+}
+
+/// Reload the policy of `engine` on SIGHUP or when `path` is modified.
+///
+/// An invalid file is rejected and the previous policy is kept.
+pub fn watch(path: PathBuf, engine: Arc<Engine>) {
+    task::spawn(async move {
+        let mut hangup = signal(SignalKind::hangup())
+            .map_err(|error| {
+                error!(
+                    error = error.to_string(),
+                    "Cannot listen to SIGHUP, the policy is only reloaded when its file changes."
+                )
+            })
+            .ok();
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        let mut modified = modified_at(&path);
+
+        loop {
+            tokio::select! {
+                Some(_) = async {
+                    match hangup.as_mut() {
+                        Some(hangup) => hangup.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    info!("SIGHUP received, reloading sanction policy.");
+                },
+                _ = interval.tick() => {
+                    let last = modified_at(&path);
+                    if last == modified {
+                        continue;
+                    }
+                    modified = last;
+                },
+            }
+
+            reload(&path, &engine);
+        }
+    });
+}
+
+fn reload(path: &Path, engine: &Engine) {
+    match Policy::from_file(path) {
+        Ok(policy) => {
+            info!(rules = policy.rules.len(), "Sanction policy reloaded.");
+            engine.replace(policy);
+        },
+        Err(error) => {
+            error!(
+                error = error.to_string(),
+                "Sanction policy rejected, keeping the previous one."
+            );
+        },
+    }
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_accept_every_unit() {
+        for (window, expected) in [
+            ("30s", Duration::seconds(30)),
+            ("15m", Duration::minutes(15)),
+            ("24h", Duration::hours(24)),
+            ("7d", Duration::days(7)),
+        ] {
+            assert_eq!(parse_window(window), Ok(expected), "{}", window);
+        }
+    }
+
+    #[test]
+    fn invalid_windows_are_rejected() {
+        for window in [
+            "",
+            "h",
+            "0h",
+            "-1h",
+            "1.5h",
+            "5",
+            "5w",
+            "5µ",
+            "µ",
+            "9999999999999d",
+        ] {
+            assert!(parse_window(window).is_err(), "{:?}", window);
+        }
+    }
+
+    #[test]
+    fn invalid_windows_are_policy_errors() {
+        let error = r#"
+            [[rules]]
+            reason = "Nudity"
+            threshold = 5
+            window = "5µ"
+            sanction = "Removal"
+        "#
+        .parse::<Policy>()
+        .unwrap_err();
+
+        assert_eq!(error.0, vec!["rule #1: unknown unit in window \"5µ\""]);
+    }
+}
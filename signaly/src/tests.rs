@@ -0,0 +1,473 @@
+//! End-to-end processing of events with the in-memory broker and storage.
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::Value;
+use signaly_db::broker::{Acknowledgement, Origin};
+use signaly_db::memory::{MemoryBroker, MemoryStorage};
+use signaly_db::storage::Storage;
+
+use crate::broker::DeadLetterQueue;
+use crate::cli::ReplayArgs;
+use crate::helpers;
+use crate::models::{DeadLetterEnvelope, Status};
+use crate::pipeline::Pipeline;
+use crate::replay;
+use crate::sanction::{Engine, Policy};
+
+const POLICY: &str = r#"
+[[rules]]
+reason = "Nudity"
+threshold = 3
+window = "24h"
+sanction = "Removal"
+"#;
+
+/// Consume `compliance` topic of a new broker with a new storage.
+fn start() -> (MemoryBroker, MemoryStorage) {
+    let broker = MemoryBroker::new();
+    let storage = consume(&broker, vec![broker.clone()], None);
+
+    (broker, storage)
+}
+
+/// Consume `compliance` topic of `broker` with a new storage, publishing
+/// sanctions on `publishers` and invalid messages on `dead_letters` topic.
+fn consume(
+    broker: &MemoryBroker,
+    publishers: Vec<MemoryBroker>,
+    dead_letters: Option<&str>,
+) -> MemoryStorage {
+    let storage = MemoryStorage::new();
+
+    let pipeline = Arc::new(Pipeline::new(
+        Arc::new(storage.clone()),
+        Arc::new(Engine::new(POLICY.parse::<Policy>().unwrap())),
+        publishers.into(),
+        "sanction".to_string(),
+    ));
+    helpers::consume_messages(
+        broker.subscribe("compliance"),
+        pipeline,
+        dead_letters.map(|topic| DeadLetterQueue {
+            publisher: broker.clone(),
+            destination: topic.to_string(),
+        }),
+    );
+
+    storage
+}
+
+/// Report of `Nudity` against `target`.
+fn report(id: &str, target: &str) -> String {
+    report_from("https://www.gravitalia.com/x", id, target)
+}
+
+/// Report of `Nudity` against `target` sent by `source`.
+fn report_from(source: &str, id: &str, target: &str) -> String {
+    serde_json::json!({
+        "specversion": "1.0",
+        "type": "com.gravitalia.report.add",
+        "source": source,
+        "id": id,
+        "time": Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        "datacontenttype": "application/json",
+        "data": {
+            "from": "x",
+            "to": target,
+            "reason": "Nudity"
+        }
+    })
+    .to_string()
+}
+
+/// Wait until `count` messages have been acknowledged.
+async fn acknowledged(
+    broker: &MemoryBroker,
+    count: usize,
+) -> Vec<Acknowledgement> {
+    for _ in 0..500 {
+        let acknowledgements = broker.acknowledgements();
+        if acknowledgements.len() >= count {
+            return acknowledgements
+                .into_iter()
+                .map(|(_, acknowledgement)| acknowledgement)
+                .collect();
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    panic!("{} messages were not acknowledged", count);
+}
+
+/// Wait until `condition` holds.
+async fn eventually(condition: impl Fn() -> bool) {
+    for _ in 0..500 {
+        if condition() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    panic!("condition never held");
+}
+
+#[tokio::test]
+async fn reports_over_threshold_take_a_sanction() {
+    let (broker, storage) = start();
+
+    for id in ["1", "2"] {
+        broker.push("compliance", report(id, "111111111"));
+    }
+    acknowledged(&broker, 2).await;
+    assert!(broker.published("sanction").is_empty());
+
+    broker.push("compliance", report("3", "111111111"));
+    let acknowledgements = acknowledged(&broker, 3).await;
+
+    assert_eq!(acknowledgements, vec![Acknowledgement::Ack; 3]);
+    assert_eq!(storage.reports().len(), 3);
+    assert_eq!(storage.sanctions().len(), 1);
+
+    let published = broker.published("sanction");
+    assert_eq!(published.len(), 1);
+    assert_eq!(published[0].key.as_deref(), Some("111111111"));
+    assert_eq!(published[0].routing_key, "sanction.removal");
+
+    let message: Value = serde_json::from_str(&published[0].content).unwrap();
+    assert_eq!(message["data"]["to"], "111111111");
+    assert_eq!(message["data"]["reason"], "Nudity");
+    assert_eq!(message["data"]["sanction"], "Removal");
+}
+
+#[tokio::test]
+async fn sanctioned_targets_are_not_sanctioned_again() {
+    let (broker, storage) = start();
+
+    for id in ["1", "2", "3", "4", "5"] {
+        broker.push("compliance", report(id, "111111111"));
+    }
+    acknowledged(&broker, 5).await;
+
+    assert_eq!(storage.sanctions().len(), 1);
+    assert_eq!(broker.published("sanction").len(), 1);
+}
+
+#[tokio::test]
+async fn revoked_sanctions_are_taken_again_over_threshold() {
+    let (broker, storage) = start();
+
+    for id in ["1", "2", "3"] {
+        broker.push("compliance", report(id, "111111111"));
+    }
+    acknowledged(&broker, 3).await;
+
+    let mut sanction = storage.sanctions().remove(0);
+    sanction.status = Some(Status::Revoked.code());
+    storage.insert_sanction(&sanction).await.unwrap();
+
+    broker.push("compliance", report("4", "111111111"));
+    acknowledged(&broker, 4).await;
+
+    assert_eq!(storage.sanctions().len(), 2);
+    assert_eq!(broker.published("sanction").len(), 2);
+}
+
+#[tokio::test]
+async fn reports_against_other_targets_are_not_counted() {
+    let (broker, storage) = start();
+
+    for (id, target) in [("1", "a"), ("2", "b"), ("3", "c")] {
+        broker.push("compliance", report(id, target));
+    }
+    acknowledged(&broker, 3).await;
+
+    assert_eq!(storage.reports().len(), 3);
+    assert!(storage.sanctions().is_empty());
+    assert!(broker.published("sanction").is_empty());
+}
+
+#[tokio::test]
+async fn duplicate_events_are_ignored() {
+    let (broker, storage) = start();
+
+    for _ in 0..3 {
+        broker.push("compliance", report("1", "111111111"));
+    }
+    let acknowledgements = acknowledged(&broker, 3).await;
+
+    assert_eq!(acknowledgements, vec![Acknowledgement::Ack; 3]);
+    assert_eq!(storage.reports().len(), 1);
+    assert!(broker.published("sanction").is_empty());
+}
+
+#[tokio::test]
+async fn sanctions_are_sent_again_to_failed_brokers_only() {
+    let broker = MemoryBroker::new();
+    let other = MemoryBroker::named("other");
+    let storage = consume(&broker, vec![broker.clone(), other.clone()], None);
+    other.set_available(false);
+
+    for id in ["1", "2", "3"] {
+        broker.push("compliance", report(id, "111111111"));
+    }
+    let acknowledgements = acknowledged(&broker, 3).await;
+    assert_eq!(acknowledgements[2], Acknowledgement::Requeue);
+    assert_eq!(broker.published("sanction").len(), 1);
+    assert!(other.published("sanction").is_empty());
+
+    other.set_available(true);
+    eventually(|| {
+        broker.acknowledgements().last().map(|(_, ack)| *ack)
+            == Some(Acknowledgement::Ack)
+    })
+    .await;
+
+    let id = |broker: &MemoryBroker| {
+        let published = broker.published("sanction");
+        assert_eq!(published.len(), 1);
+        serde_json::from_str::<Value>(&published[0].content).unwrap()["id"]
+            .clone()
+    };
+    assert_eq!(id(&broker), id(&other));
+    assert_eq!(storage.sanctions().len(), 1);
+}
+
+#[tokio::test]
+async fn same_id_from_other_sources_is_another_event() {
+    let (broker, storage) = start();
+
+    let id = "9f0c3b9e-2f4a-4a8e-9c1d-6f1e2d3c4b5a";
+    for source in ["https://a.gravitalia.com", "https://b.gravitalia.com"] {
+        broker.push("compliance", report_from(source, id, "111111111"));
+    }
+    acknowledged(&broker, 2).await;
+
+    let reports = storage.reports();
+    assert_eq!(reports.len(), 2);
+    assert_ne!(reports[0].id, reports[1].id);
+}
+
+#[tokio::test]
+async fn invalid_messages_are_dead_lettered() {
+    let (broker, storage) = start();
+
+    broker.push("compliance", "not json");
+    broker.push("compliance", report("", "111111111"));
+    let acknowledgements = acknowledged(&broker, 2).await;
+
+    assert_eq!(acknowledgements, vec![Acknowledgement::Ack; 2]);
+    assert!(storage.reports().is_empty());
+
+    let letters = storage.dead_letters();
+    assert_eq!(letters.len(), 2);
+    assert_eq!(letters[0].payload, b"not json");
+    assert_eq!(letters[0].topic.as_deref(), Some("compliance"));
+    assert_eq!(letters[1].offset, Some(1));
+    assert!(letters[1].error.contains("id"));
+}
+
+#[tokio::test]
+async fn failed_events_are_requeued_then_processed() {
+    let (broker, storage) = start();
+    broker.set_available(false);
+
+    for id in ["1", "2", "3"] {
+        broker.push("compliance", report(id, "111111111"));
+    }
+
+    // The sanction cannot be published, so the third report is requeued.
+    let acknowledgements = acknowledged(&broker, 3).await;
+    assert_eq!(acknowledgements[..2], [Acknowledgement::Ack; 2]);
+    assert_eq!(acknowledgements[2], Acknowledgement::Requeue);
+    assert!(broker.published("sanction").is_empty());
+
+    broker.set_available(true);
+    eventually(|| {
+        broker.acknowledgements().last().map(|(_, ack)| *ack)
+            == Some(Acknowledgement::Ack)
+    })
+    .await;
+
+    // The redelivered report is not ignored as a duplicate.
+    let published = broker.published("sanction");
+    assert_eq!(published.len(), 1);
+    assert_eq!(storage.reports().len(), 3);
+
+    // The sanction is saved once, with the identifier of its message.
+    let sanctions = storage.sanctions();
+    assert_eq!(sanctions.len(), 1);
+    let message: Value = serde_json::from_str(&published[0].content).unwrap();
+    assert_eq!(message["id"], sanctions[0].id.to_string());
+}
+
+#[tokio::test]
+async fn dead_letters_are_not_requeued_when_the_broker_is_down() {
+    let (broker, storage) = start();
+    broker.set_available(false);
+
+    broker.push("compliance", "not json");
+    let acknowledgements = acknowledged(&broker, 1).await;
+
+    assert_eq!(acknowledgements, [Acknowledgement::Ack]);
+    assert_eq!(storage.dead_letters().len(), 1);
+}
+
+#[tokio::test]
+async fn invalid_messages_are_sent_to_the_dead_letter_queue() {
+    let broker = MemoryBroker::new();
+    let storage = consume(&broker, vec![broker.clone()], Some("dead-letters"));
+
+    broker.push("compliance", "not json");
+    assert_eq!(acknowledged(&broker, 1).await, [Acknowledgement::Ack]);
+
+    let published = broker.published("dead-letters");
+    assert_eq!(published.len(), 1);
+    let envelope: DeadLetterEnvelope =
+        serde_json::from_str(&published[0].content).unwrap();
+    assert_eq!(envelope.payload.as_deref(), Some("not json"));
+    assert_eq!(envelope.topic, "compliance");
+    assert_eq!(envelope.offset, Some(0));
+    assert!(!envelope.error.is_empty());
+
+    // Also indexed in storage.
+    assert_eq!(storage.dead_letters().len(), 1);
+}
+
+#[tokio::test]
+async fn dead_letters_are_requeued_until_sent_to_the_queue() {
+    let broker = MemoryBroker::new();
+    let storage = consume(&broker, vec![broker.clone()], Some("dead-letters"));
+    broker.set_available(false);
+
+    broker.push("compliance", "not json");
+    assert_eq!(acknowledged(&broker, 1).await, [Acknowledgement::Requeue]);
+    assert!(storage.dead_letters().is_empty());
+
+    broker.set_available(true);
+    eventually(|| {
+        broker.acknowledgements().last().map(|(_, ack)| *ack)
+            == Some(Acknowledgement::Ack)
+    })
+    .await;
+
+    assert_eq!(broker.published("dead-letters").len(), 1);
+    assert_eq!(storage.dead_letters().len(), 1);
+}
+
+/// Pipeline of a new storage with a policy sanctioning five reports,
+/// publishing sanctions on `publishers`.
+fn replay_pipeline(
+    publishers: Vec<MemoryBroker>,
+) -> (Pipeline<MemoryStorage, MemoryBroker>, MemoryStorage) {
+    const POLICY: &str = r#"
+    [[rules]]
+    reason = "Nudity"
+    threshold = 5
+    window = "24h"
+    sanction = "Removal"
+    "#;
+
+    let storage = MemoryStorage::new();
+    let pipeline = Pipeline::new(
+        Arc::new(storage.clone()),
+        Arc::new(Engine::new(POLICY.parse::<Policy>().unwrap())),
+        publishers.into(),
+        "sanction".to_string(),
+    );
+
+    (pipeline, storage)
+}
+
+/// Send `payload` to `dead-letters` topic of `broker`, as if rejected a
+/// minute ago.
+fn dead_letter(broker: &MemoryBroker, payload: &str) {
+    let envelope = DeadLetterEnvelope::new(
+        payload.as_bytes(),
+        &Origin {
+            topic: "compliance".to_string(),
+            ..Default::default()
+        },
+        "rejected".to_string(),
+        Utc::now() - chrono::Duration::minutes(1),
+    );
+
+    broker.push("dead-letters", serde_json::to_string(&envelope).unwrap());
+}
+
+/// Arguments of `signaly replay`, with `--dry-run` if `dry_run`.
+fn replay_args(dry_run: bool) -> ReplayArgs {
+    ReplayArgs {
+        file: None,
+        r#type: None,
+        source: None,
+        from: None,
+        to: None,
+        dry_run,
+    }
+}
+
+#[tokio::test]
+async fn dry_runs_count_replayed_reports() {
+    let broker = MemoryBroker::new();
+    let (pipeline, storage) = replay_pipeline(Vec::new());
+    let subscriber = broker.subscribe("dead-letters");
+
+    for id in 1..=5 {
+        dead_letter(&broker, &report(&id.to_string(), "111111111"));
+    }
+
+    let summary =
+        replay::run_queue(&pipeline, subscriber, None, &replay_args(true))
+            .await
+            .unwrap();
+
+    assert_eq!(summary.accepted, 5);
+    assert_eq!(summary.sanctions, 1);
+    assert!(storage.reports().is_empty());
+    assert!(broker.acknowledgements().is_empty());
+}
+
+#[tokio::test]
+async fn replayed_dead_letters_are_acknowledged_and_invalid_ones_kept() {
+    let broker = MemoryBroker::new();
+    let (pipeline, storage) = replay_pipeline(vec![broker.clone()]);
+    let subscriber = broker.subscribe("dead-letters");
+    let queue = DeadLetterQueue {
+        publisher: broker.clone(),
+        destination: "dead-letters".to_string(),
+    };
+
+    dead_letter(&broker, &report("1", "111111111"));
+    dead_letter(&broker, "not json");
+
+    let summary = replay::run_queue(
+        &pipeline,
+        subscriber,
+        Some(&queue),
+        &replay_args(false),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(summary.accepted, 1);
+    assert_eq!(summary.invalid, 1);
+    assert_eq!(storage.reports().len(), 1);
+    assert_eq!(
+        broker
+            .acknowledgements()
+            .into_iter()
+            .map(|(_, ack)| ack)
+            .collect::<Vec<_>>(),
+        [Acknowledgement::Ack; 2]
+    );
+
+    // The invalid message is sent back once.
+    let published = broker.published("dead-letters");
+    assert_eq!(published.len(), 3);
+    let kept: DeadLetterEnvelope =
+        serde_json::from_str(&published[2].content).unwrap();
+    assert_eq!(kept.payload.as_deref(), Some("not json"));
+    assert!(kept.replayed_at.is_some());
+}
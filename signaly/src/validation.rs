@@ -0,0 +1,497 @@
+//! Validation of received messages against CloudEvents 1.0.2 and
+//! `docs/report_instructions.md`.
+//!
+//! Every invalid field is reported, rather than only the first one.
+
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::models::{Event, Reason, Sanction, Type};
+
+/// Only supported version of CloudEvents specification.
+const SPEC_VERSION: &str = "1.0";
+/// Only supported content type of `data`.
+const CONTENT_TYPE: &str = "application/json";
+
+/// Time an event can be ahead of our clock.
+const MAX_CLOCK_SKEW: Duration = Duration::minutes(5);
+
+/// Rule broken by a field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Violation {
+    /// Required field is not set.
+    Missing,
+    /// Field does not have the expected JSON type.
+    WrongType(&'static str),
+    /// String is empty.
+    Empty,
+    /// `specversion` is not `1.0`.
+    UnsupportedSpecVersion,
+    /// Timestamp is not in RFC 3339 format.
+    InvalidTime,
+    /// Timestamp is ahead of the clock by more than [`MAX_CLOCK_SKEW`].
+    FutureTime,
+    /// `datacontenttype` is not `application/json`.
+    UnsupportedContentType,
+    /// Value is not one of the predetermined values.
+    UnknownValue,
+}
+
+#[cfg(feature = "telemetry")]
+impl Violation {
+    /// Short name used as metric label.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Violation::Missing => "missing",
+            Violation::WrongType(_) => "wrong_type",
+            Violation::Empty => "empty",
+            Violation::UnsupportedSpecVersion => "unsupported_specversion",
+            Violation::InvalidTime => "invalid_time",
+            Violation::FutureTime => "future_time",
+            Violation::UnsupportedContentType => "unsupported_datacontenttype",
+            Violation::UnknownValue => "unknown_value",
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Missing => write!(f, "is required"),
+            Violation::WrongType(expected) => {
+                write!(f, "must be {}", expected)
+            },
+            Violation::Empty => write!(f, "must be a non-empty string"),
+            Violation::UnsupportedSpecVersion => {
+                write!(f, "must be {:?}", SPEC_VERSION)
+            },
+            Violation::InvalidTime => write!(f, "must be in RFC 3339 format"),
+            Violation::FutureTime => write!(f, "must not be in the future"),
+            Violation::UnsupportedContentType => {
+                write!(f, "must be {:?}", CONTENT_TYPE)
+            },
+            Violation::UnknownValue => {
+                write!(f, "must be a predetermined value")
+            },
+        }
+    }
+}
+
+/// Invalid field of a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    /// Path of the field, such as `data.to`.
+    pub field: &'static str,
+    /// Rule broken by the field.
+    pub violation: Violation,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.field, self.violation)
+    }
+}
+
+/// Reason why a message is rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+    /// Message is not UTF-8 encoded JSON.
+    Malformed(String),
+    /// Message is JSON but some fields are invalid.
+    Invalid(Vec<FieldError>),
+}
+
+#[cfg(feature = "telemetry")]
+impl Rejection {
+    /// Field and reason of every error, used as metric labels.
+    pub fn labels(&self) -> Vec<(&'static str, &'static str)> {
+        match self {
+            Rejection::Malformed(_) => vec![("", "malformed")],
+            Rejection::Invalid(errors) => errors
+                .iter()
+                .map(|error| (error.field, error.violation.code()))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Malformed(error) => {
+                write!(f, "malformed message: {}", error)
+            },
+            Rejection::Invalid(errors) => {
+                for (index, error) in errors.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{}", error)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+/// Decode and validate a message received from a broker.
+pub fn parse(payload: &[u8]) -> Result<Event, Rejection> {
+    let value: Value = serde_json::from_slice(payload)
+        .map_err(|error| Rejection::Malformed(error.to_string()))?;
+
+    let errors = validate(&value);
+    if !errors.is_empty() {
+        return Err(Rejection::Invalid(errors));
+    }
+
+    serde_json::from_value(value)
+        .map_err(|error| Rejection::Malformed(error.to_string()))
+}
+
+/// Get every invalid field of a message.
+pub fn validate(value: &Value) -> Vec<FieldError> {
+    let mut checker = Checker::default();
+
+    let Some(event) = checker.object(Some(value), "event") else {
+        return checker.errors;
+    };
+
+    if let Some(version) =
+        checker.string(event.get("specversion"), "specversion")
+    {
+        if version != SPEC_VERSION {
+            checker.fail("specversion", Violation::UnsupportedSpecVersion);
+        }
+    }
+    checker.string(event.get("id"), "id");
+    checker.string(event.get("source"), "source");
+    checker.string(event.get("type"), "type");
+    if let Some(time) = checker.time(event.get("time"), "time") {
+        if time > Utc::now() + MAX_CLOCK_SKEW {
+            checker.fail("time", Violation::FutureTime);
+        }
+    }
+
+    if let Some(content_type) =
+        checker.optional_string(event.get("datacontenttype"), "datacontenttype")
+    {
+        let media_type = content_type.split(';').next().unwrap_or_default();
+        if !media_type.trim().eq_ignore_ascii_case(CONTENT_TYPE) {
+            checker.fail("datacontenttype", Violation::UnsupportedContentType);
+        }
+    }
+
+    if let Some(data) = checker.object(event.get("data"), "data") {
+        checker.string(data.get("from"), "data.from");
+        checker.string(data.get("to"), "data.to");
+        checker.value::<Type>(data.get("type"), "data.type", false);
+        checker.value::<Reason>(data.get("reason"), "data.reason", true);
+        checker.value::<Sanction>(data.get("sanction"), "data.sanction", false);
+        checker.time(data.get("expires_at"), "data.expires_at");
+    }
+
+    checker.errors
+}
+
+/// Collect errors while checking fields.
+#[derive(Default)]
+struct Checker {
+    errors: Vec<FieldError>,
+}
+
+impl Checker {
+    fn fail(&mut self, field: &'static str, violation: Violation) {
+        self.errors.push(FieldError { field, violation });
+    }
+
+    /// Required JSON object.
+    fn object<'a>(
+        &mut self,
+        value: Option<&'a Value>,
+        field: &'static str,
+    ) -> Option<&'a Map<String, Value>> {
+        match value {
+            None | Some(Value::Null) => {
+                self.fail(field, Violation::Missing);
+                None
+            },
+            Some(Value::Object(object)) => Some(object),
+            Some(_) => {
+                self.fail(field, Violation::WrongType("an object"));
+                None
+            },
+        }
+    }
+
+    /// Required non-empty string.
+    fn string<'a>(
+        &mut self,
+        value: Option<&'a Value>,
+        field: &'static str,
+    ) -> Option<&'a str> {
+        if matches!(value, None | Some(Value::Null)) {
+            self.fail(field, Violation::Missing);
+            return None;
+        }
+
+        self.optional_string(value, field)
+    }
+
+    /// Non-empty string, if set.
+    fn optional_string<'a>(
+        &mut self,
+        value: Option<&'a Value>,
+        field: &'static str,
+    ) -> Option<&'a str> {
+        match value {
+            None | Some(Value::Null) => None,
+            Some(Value::String(string)) if string.trim().is_empty() => {
+                self.fail(field, Violation::Empty);
+                None
+            },
+            Some(Value::String(string)) => Some(string),
+            Some(_) => {
+                self.fail(field, Violation::WrongType("a string"));
+                None
+            },
+        }
+    }
+
+    /// RFC 3339 timestamp, if set.
+    fn time(
+        &mut self,
+        value: Option<&Value>,
+        field: &'static str,
+    ) -> Option<DateTime<Utc>> {
+        let time = self.optional_string(value, field)?;
+        match DateTime::parse_from_rfc3339(time) {
+            Ok(time) => Some(time.with_timezone(&Utc)),
+            Err(_) => {
+                self.fail(field, Violation::InvalidTime);
+                None
+            },
+        }
+    }
+
+    /// Predetermined value deserialized as `T`.
+    fn value<T: DeserializeOwned>(
+        &mut self,
+        value: Option<&Value>,
+        field: &'static str,
+        required: bool,
+    ) {
+        match value {
+            None | Some(Value::Null) if required => {
+                self.fail(field, Violation::Missing)
+            },
+            None | Some(Value::Null) => {},
+            Some(value) => {
+                if serde_json::from_value::<T>(value.clone()).is_err() {
+                    self.fail(field, Violation::UnknownValue);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Valid report, changed by each case.
+    fn event() -> Value {
+        json!({
+            "specversion": "1.0",
+            "type": "com.gravitalia.report.add",
+            "source": "https://www.gravitalia.com/x",
+            "id": "1",
+            "time": "2024-05-01T12:00:00Z",
+            "datacontenttype": "application/json",
+            "data": {
+                "from": "x",
+                "to": "111111111",
+                "reason": "Nudity"
+            }
+        })
+    }
+
+    /// Set `value` at `path` of `event`, or remove the field if `None`.
+    fn with(path: &str, value: Option<Value>) -> Value {
+        let mut event = event();
+        let (parent, field) = match path.split_once('.') {
+            Some((parent, field)) => (&mut event[parent], field),
+            None => (&mut event, path),
+        };
+        let object = parent.as_object_mut().unwrap();
+
+        match value {
+            Some(value) => object.insert(field.to_string(), value),
+            None => object.remove(field),
+        };
+
+        event
+    }
+
+    #[test]
+    fn valid_events_are_accepted() {
+        for (path, value) in [
+            ("time", None),
+            ("datacontenttype", None),
+            (
+                "datacontenttype",
+                Some(json!("application/json; charset=utf-8")),
+            ),
+            ("time", Some(json!("2024-05-01T14:00:00+02:00"))),
+            ("data.reason", Some(json!({ "Other": "scam" }))),
+            ("data.type", Some(json!("Sanction"))),
+            ("data.sanction", Some(json!("Suspension"))),
+            ("data.expires_at", Some(json!("2024-06-01T00:00:00Z"))),
+        ] {
+            let event = with(path, value);
+
+            assert_eq!(validate(&event), vec![], "{}", event);
+            assert!(parse(event.to_string().as_bytes()).is_ok(), "{}", event);
+        }
+    }
+
+    #[test]
+    fn invalid_fields_are_reported() {
+        for (path, value, violation) in [
+            ("specversion", None, Violation::Missing),
+            (
+                "specversion",
+                Some(json!("0.3")),
+                Violation::UnsupportedSpecVersion,
+            ),
+            (
+                "specversion",
+                Some(json!(1.0)),
+                Violation::WrongType("a string"),
+            ),
+            ("id", None, Violation::Missing),
+            ("id", Some(json!(null)), Violation::Missing),
+            ("id", Some(json!("")), Violation::Empty),
+            ("id", Some(json!("  ")), Violation::Empty),
+            ("id", Some(json!(1)), Violation::WrongType("a string")),
+            ("source", None, Violation::Missing),
+            ("source", Some(json!("")), Violation::Empty),
+            ("type", None, Violation::Missing),
+            ("type", Some(json!("")), Violation::Empty),
+            ("time", Some(json!("yesterday")), Violation::InvalidTime),
+            (
+                "time",
+                Some(json!("2024-05-01 12:00:00")),
+                Violation::InvalidTime,
+            ),
+            ("time", Some(json!("")), Violation::Empty),
+            (
+                "time",
+                Some(json!("2999-01-01T00:00:00Z")),
+                Violation::FutureTime,
+            ),
+            (
+                "datacontenttype",
+                Some(json!("text/plain")),
+                Violation::UnsupportedContentType,
+            ),
+            ("data", None, Violation::Missing),
+            (
+                "data",
+                Some(json!("report")),
+                Violation::WrongType("an object"),
+            ),
+            ("data.from", None, Violation::Missing),
+            ("data.to", Some(json!("")), Violation::Empty),
+            ("data.reason", None, Violation::Missing),
+            (
+                "data.reason",
+                Some(json!("Rudeness")),
+                Violation::UnknownValue,
+            ),
+            ("data.reason", Some(json!(4)), Violation::UnknownValue),
+            (
+                "data.reason",
+                Some(json!({ "Other": 1 })),
+                Violation::UnknownValue,
+            ),
+            ("data.type", Some(json!("Warning")), Violation::UnknownValue),
+            ("data.sanction", Some(json!("Ban")), Violation::UnknownValue),
+            (
+                "data.expires_at",
+                Some(json!("never")),
+                Violation::InvalidTime,
+            ),
+        ] {
+            let event = with(path, value);
+
+            assert_eq!(
+                validate(&event),
+                vec![FieldError {
+                    field: path,
+                    violation
+                }],
+                "{}",
+                event
+            );
+            assert!(matches!(
+                parse(event.to_string().as_bytes()),
+                Err(Rejection::Invalid(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let event = json!({
+            "specversion": "2.0",
+            "id": "",
+            "source": "x",
+            "type": "x",
+            "data": { "to": "y", "reason": "Rudeness" }
+        });
+
+        assert_eq!(
+            validate(&event),
+            vec![
+                FieldError {
+                    field: "specversion",
+                    violation: Violation::UnsupportedSpecVersion,
+                },
+                FieldError {
+                    field: "id",
+                    violation: Violation::Empty,
+                },
+                FieldError {
+                    field: "data.from",
+                    violation: Violation::Missing,
+                },
+                FieldError {
+                    field: "data.reason",
+                    violation: Violation::UnknownValue,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected() {
+        for payload in [&b"not json"[..], b"", b"\xff\xfe", b"[1, 2"] {
+            assert!(matches!(parse(payload), Err(Rejection::Malformed(_))));
+        }
+
+        assert_eq!(
+            validate(&json!([])),
+            vec![FieldError {
+                field: "event",
+                violation: Violation::WrongType("an object"),
+            }]
+        );
+    }
+}